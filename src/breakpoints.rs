@@ -0,0 +1,502 @@
+use std::fmt;
+
+use crate::cpu::Cpu;
+
+/// something the cpu does that a breakpoint can stop on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// the program counter reached an address
+    Execute,
+    Read,
+    Write,
+    /// IN from a port
+    In,
+    /// OUT to a port
+    Out,
+}
+
+impl Event {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// which events a breakpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Execute,
+    Read,
+    Write,
+    /// reads and writes
+    Access,
+    In,
+    Out,
+    /// IN and OUT
+    Io,
+}
+
+impl Kind {
+    fn events(self) -> u8 {
+        match self {
+            Kind::Execute => Event::Execute.bit(),
+            Kind::Read => Event::Read.bit(),
+            Kind::Write => Event::Write.bit(),
+            Kind::Access => Event::Read.bit() | Event::Write.bit(),
+            Kind::In => Event::In.bit(),
+            Kind::Out => Event::Out.bit(),
+            Kind::Io => Event::In.bit() | Event::Out.bit(),
+        }
+    }
+}
+
+/// the left hand side of a condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    Psw,
+    // flags, 0 or 1
+    Z,
+    S,
+    P,
+    Cy,
+    Ac,
+    /// the byte read, written or transferred by the access that triggered the breakpoint
+    Value,
+    /// the byte in memory at an address
+    Memory(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// a comparison like `A == 0x10` that must hold for a breakpoint to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    /// parse `<operand> <op> <value>`, where the operand is a register, a flag
+    /// (`z`, `s`, `p`, `cy`, `ac`), `value` or a memory byte like `[20F8]`,
+    /// and the value is hex with an optional `0x` or `$` prefix
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (operand, comparison, value) = match words.as_slice() {
+            [operand, comparison, value] => (*operand, *comparison, *value),
+            _ => return Err(format!("expected <operand> <op> <value>, got: {}", text)),
+        };
+        let operand = match operand.to_ascii_lowercase().as_str() {
+            "a" => Operand::A,
+            "b" => Operand::B,
+            "c" => Operand::C,
+            "d" => Operand::D,
+            "e" => Operand::E,
+            "h" => Operand::H,
+            "l" => Operand::L,
+            "bc" => Operand::BC,
+            "de" => Operand::DE,
+            "hl" => Operand::HL,
+            "sp" => Operand::SP,
+            "pc" => Operand::PC,
+            "psw" | "f" => Operand::Psw,
+            "z" => Operand::Z,
+            "s" => Operand::S,
+            "p" => Operand::P,
+            "cy" => Operand::Cy,
+            "ac" => Operand::Ac,
+            "value" => Operand::Value,
+            other => match other.strip_prefix('[').and_then(|o| o.strip_suffix(']')) {
+                Some(address) => Operand::Memory(parse_hex(address)?),
+                None => return Err(format!("unknown operand: {}", operand)),
+            },
+        };
+        let comparison = match comparison {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => return Err(format!("unknown comparison: {}", comparison)),
+        };
+        Ok(Condition {
+            operand,
+            comparison,
+            value: parse_hex(value)?,
+        })
+    }
+
+    /// `value` is the byte involved in the access, if any
+    pub fn holds(&self, cpu: &Cpu, value: Option<u8>) -> bool {
+        let flags = cpu.flags();
+        let lhs = match self.operand {
+            Operand::A => cpu.a as u16,
+            Operand::B => cpu.b as u16,
+            Operand::C => cpu.c as u16,
+            Operand::D => cpu.d as u16,
+            Operand::E => cpu.e as u16,
+            Operand::H => cpu.h as u16,
+            Operand::L => cpu.l as u16,
+            Operand::BC => cpu.bc(),
+            Operand::DE => cpu.de(),
+            Operand::HL => cpu.hl(),
+            Operand::SP => cpu.sp,
+            Operand::PC => cpu.pc,
+            Operand::Psw => cpu.psw() as u16,
+            Operand::Z => flags.z as u16,
+            Operand::S => flags.s as u16,
+            Operand::P => flags.p as u16,
+            Operand::Cy => flags.cy as u16,
+            Operand::Ac => flags.ac as u16,
+            Operand::Value => match value {
+                Some(value) => value as u16,
+                None => return false,
+            },
+            Operand::Memory(address) => cpu.read_byte(address) as u16,
+        };
+        match self.comparison {
+            Comparison::Eq => lhs == self.value,
+            Comparison::Ne => lhs != self.value,
+            Comparison::Lt => lhs < self.value,
+            Comparison::Le => lhs <= self.value,
+            Comparison::Gt => lhs > self.value,
+            Comparison::Ge => lhs >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operand {
+            Operand::Z | Operand::S | Operand::P | Operand::Cy | Operand::Ac | Operand::Value => {
+                write!(f, "{}", format!("{:?}", self.operand).to_ascii_lowercase())?
+            }
+            Operand::Memory(address) => write!(f, "[{:04X}]", address)?,
+            operand => write!(f, "{}", format!("{:?}", operand).to_ascii_uppercase())?,
+        }
+        let comparison = match self.comparison {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, " {} {:#X}", comparison, self.value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: Kind,
+    /// first address or port covered
+    pub start: u16,
+    /// last address or port covered, inclusive
+    pub end: u16,
+    pub condition: Option<Condition>,
+    /// only stop once the breakpoint has been hit this many times
+    pub count: u64,
+    /// times the address matched and the condition held
+    pub hits: u64,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = format!("{:?}", self.kind).to_ascii_lowercase();
+        write!(f, "{:<3} {:<7} {:04X}", self.id, kind, self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        if self.count > 1 {
+            write!(f, " count {}", self.count)?;
+        }
+        write!(f, ", hit {} times", self.hits)
+    }
+}
+
+/// a breakpoint that stopped execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub id: usize,
+    pub event: Event,
+    pub address: u16,
+    /// the byte read, written or transferred, none for execution breakpoints
+    pub value: Option<u8>,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.event {
+            Event::Execute => "reached",
+            Event::Read => "read from",
+            Event::Write => "write to",
+            Event::In => "IN from port",
+            Event::Out => "OUT to port",
+        };
+        write!(f, "breakpoint {}: {} {:04X}", self.id, what, self.address)?;
+        if let Some(value) = self.value {
+            write!(f, ", value {:02X}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// the breakpoints of a cpu, with a mask of the events any of them watch so
+/// the cpu only pays for a bit test when none are set
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    events: u8,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// add a breakpoint covering `start..=end` and return its id
+    pub fn add(
+        &mut self,
+        kind: Kind,
+        start: u16,
+        end: u16,
+        condition: Option<Condition>,
+        count: u64,
+    ) -> usize {
+        self.next_id += 1;
+        self.list.push(Breakpoint {
+            id: self.next_id,
+            kind,
+            start: start.min(end),
+            end: start.max(end),
+            condition,
+            count,
+            hits: 0,
+        });
+        self.update_events();
+        self.next_id
+    }
+
+    /// returns false if there is no breakpoint with that id
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|breakpoint| breakpoint.id != id);
+        self.update_events();
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.events = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// true if any breakpoint stops on `event`
+    #[inline]
+    pub fn watches(&self, event: Event) -> bool {
+        self.events & event.bit() != 0
+    }
+
+    /// ids of the breakpoints whose range covers `address` for `event`
+    /// and whose condition holds
+    pub(crate) fn matching(
+        &self,
+        cpu: &Cpu,
+        event: Event,
+        address: u16,
+        value: Option<u8>,
+    ) -> Vec<usize> {
+        self.list
+            .iter()
+            .enumerate()
+            .filter(|(_, breakpoint)| {
+                breakpoint.kind.events() & event.bit() != 0
+                    && (breakpoint.start..=breakpoint.end).contains(&address)
+                    && breakpoint
+                        .condition
+                        .is_none_or(|condition| condition.holds(cpu, value))
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// count a hit on the breakpoint at `index`, returns whether it should stop
+    pub(crate) fn hit(&mut self, index: usize) -> Option<usize> {
+        let breakpoint = &mut self.list[index];
+        breakpoint.hits += 1;
+        (breakpoint.hits >= breakpoint.count).then_some(breakpoint.id)
+    }
+
+    fn update_events(&mut self) {
+        self.events = self
+            .list
+            .iter()
+            .fold(0, |events, breakpoint| events | breakpoint.kind.events());
+    }
+}
+
+/// parse a hex number with an optional `0x` or `$` prefix
+pub(crate) fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("not a hex number: {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakpoints, Condition, Event, Hit, Kind};
+    use crate::cpu::Cpu;
+
+    fn run(cpu: &mut Cpu, limit: usize) -> Option<Hit> {
+        for _ in 0..limit {
+            cpu.step();
+            if let Some(hit) = cpu.take_hit() {
+                return Some(hit);
+            }
+        }
+        None
+    }
+
+    // LXI SP,0x2400; LXI H,0x2000; loop: INR M; MOV A,M; OUT 3; IN 1; JMP loop
+    const PROGRAM: [u8; 14] = [
+        0x31, 0x00, 0x24, 0x21, 0x00, 0x20, 0x34, 0x7E, 0xD3, 0x03, 0xDB, 0x01, 0xC3, 0x06,
+    ];
+
+    fn start() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load(&PROGRAM, 0);
+        cpu.load(&[0x00], 0x000E);
+        cpu
+    }
+
+    #[test]
+    fn test_no_breakpoints() {
+        let breakpoints = Breakpoints::default();
+        for event in [
+            Event::Execute,
+            Event::Read,
+            Event::Write,
+            Event::In,
+            Event::Out,
+        ] {
+            assert!(!breakpoints.watches(event));
+        }
+        let mut cpu = start();
+        assert_eq!(run(&mut cpu, 1000), None);
+    }
+
+    #[test]
+    fn test_execute_breakpoint() {
+        let mut cpu = start();
+        let id = cpu.breakpoints.add(Kind::Execute, 0x0008, 0x0008, None, 1);
+        let hit = run(&mut cpu, 100).unwrap();
+        assert_eq!(hit.id, id);
+        assert_eq!(hit.event, Event::Execute);
+        assert_eq!(cpu.pc, 0x0008);
+        // stops again on the next pass through the loop
+        assert_eq!(run(&mut cpu, 100).unwrap().address, 0x0008);
+        assert_eq!(cpu.read_byte(0x2000), 2);
+    }
+
+    #[test]
+    fn test_condition_and_count() {
+        let mut cpu = start();
+        let condition = Condition::parse("a >= 3").unwrap();
+        cpu.breakpoints
+            .add(Kind::Execute, 0x0008, 0x0008, Some(condition), 2);
+        run(&mut cpu, 1000).unwrap();
+        // the condition first holds with a == 3, the count makes it stop on the second time
+        assert_eq!(cpu.a, 4);
+    }
+
+    #[test]
+    fn test_write_watchpoint_range() {
+        let mut cpu = start();
+        cpu.breakpoints.add(
+            Kind::Write,
+            0x1FF0,
+            0x2000,
+            Some(Condition::parse("value == 5").unwrap()),
+            1,
+        );
+        let hit = run(&mut cpu, 1000).unwrap();
+        assert_eq!(hit.event, Event::Write);
+        assert_eq!(hit.address, 0x2000);
+        assert_eq!(hit.value, Some(5));
+    }
+
+    #[test]
+    fn test_read_and_io_breakpoints() {
+        let mut cpu = start();
+        cpu.breakpoints.add(Kind::Read, 0x2000, 0x2000, None, 1);
+        let hit = run(&mut cpu, 100).unwrap();
+        // INR M reads before it writes
+        assert_eq!((hit.event, hit.value), (Event::Read, Some(0)));
+
+        let mut cpu = start();
+        cpu.ports[1] = 0x99;
+        let id = cpu.breakpoints.add(Kind::Io, 0x01, 0x03, None, 1);
+        assert_eq!(run(&mut cpu, 100).unwrap().event, Event::Out);
+        let hit = run(&mut cpu, 100).unwrap();
+        assert_eq!(hit.event, Event::In);
+        assert_eq!(hit.value, Some(0x99));
+        assert!(cpu.breakpoints.remove(id));
+        assert!(!cpu.breakpoints.watches(Event::In));
+        assert_eq!(run(&mut cpu, 100), None);
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            Condition::parse("A == 0x10").unwrap().to_string(),
+            "A == 0x10"
+        );
+        assert_eq!(
+            Condition::parse("[20f8] != 0").unwrap().to_string(),
+            "[20F8] != 0x0"
+        );
+        assert_eq!(
+            Condition::parse("cy == 1").unwrap().to_string(),
+            "cy == 0x1"
+        );
+        // the same hex as the debugger's addresses
+        assert_eq!(
+            Condition::parse("[$20F8] == $FF").unwrap().to_string(),
+            "[20F8] == 0xFF"
+        );
+        assert!(Condition::parse("q == 1").is_err());
+        assert!(Condition::parse("a = 1").is_err());
+        assert!(Condition::parse("a ==").is_err());
+    }
+}
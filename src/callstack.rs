@@ -0,0 +1,221 @@
+use std::{collections::VecDeque, fmt};
+
+/// frames deeper than this are dropped from the bottom, runaway recursion
+/// should not grow the shadow stack forever
+pub(crate) const MAX_FRAMES: usize = 1024;
+/// how many of the most recent mismatched returns are remembered
+const MAX_MISMATCHES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// CALL or a taken conditional call
+    Call,
+    /// an RST instruction in the program
+    Restart,
+    /// an RST pushed by an interrupt
+    Interrupt,
+}
+
+/// one return address on the shadow call stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// address of the call instruction, or of the instruction an interrupt came before
+    pub caller: u16,
+    pub target: u16,
+    pub return_address: u16,
+    /// where the return address was pushed
+    pub sp: u16,
+}
+
+/// a return that did not match the shadow call stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    /// address of the return instruction
+    pub pc: u16,
+    /// where the return address was popped from
+    pub sp: u16,
+    /// the return address pushed at `sp`, `None` if nothing was called with that stack pointer
+    pub expected: Option<u16>,
+    /// the address actually returned to
+    pub actual: u16,
+    /// frames dropped because the stack was unwound past them without returning
+    pub abandoned: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RET at {:04X} returned to {:04X}", self.pc, self.actual)?;
+        match self.expected {
+            Some(expected) if expected != self.actual => write!(f, ", expected {:04X}", expected)?,
+            Some(_) => {}
+            None => write!(f, ", no call pushed SP {:04X}", self.sp)?,
+        }
+        if self.abandoned > 0 {
+            write!(f, ", {} frames abandoned", self.abandoned)?;
+        }
+        Ok(())
+    }
+}
+
+/// calls, restarts and interrupts the cpu has not returned from yet, kept alongside
+/// the real stack so a backtrace does not have to guess which stack words are
+/// return addresses
+#[derive(Debug, Default, Clone)]
+pub struct CallStack {
+    /// outermost first
+    frames: VecDeque<Frame>,
+    mismatches: VecDeque<Mismatch>,
+}
+
+impl CallStack {
+    /// all frames, outermost first, including any the program has since
+    /// unwound past without a return
+    pub fn frames(&self) -> &VecDeque<Frame> {
+        &self.frames
+    }
+
+    /// the frames still on the real stack with stack pointer `sp`, innermost first
+    pub fn active(&self, sp: u16) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev().filter(move |frame| frame.sp >= sp)
+    }
+
+    /// the most recent returns that did not match a call, oldest first
+    pub fn mismatches(&self) -> impl Iterator<Item = &Mismatch> {
+        self.mismatches.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.mismatches.clear();
+    }
+
+    /// returns the outermost frame if it had to be dropped to make room
+    pub(crate) fn push(&mut self, frame: Frame) -> Option<Frame> {
+        let dropped = if self.frames.len() == MAX_FRAMES {
+            self.frames.pop_front()
+        } else {
+            None
+        };
+        self.frames.push_back(frame);
+        dropped
+    }
+
+    /// the frames a return popping from `sp` will remove, outermost first
+    pub(crate) fn removed_by_return(&self, sp: u16) -> Vec<Frame> {
+        let kept = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.sp > sp)
+            .map_or(0, |index| index + 1);
+        self.frames.range(kept..).copied().collect()
+    }
+
+    /// match a return of `actual`, popped from `sp` by the instruction at `pc`,
+    /// returns true if it was recorded as a mismatch
+    pub(crate) fn pop(&mut self, pc: u16, sp: u16, actual: u16) -> bool {
+        let abandoned = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| frame.sp < sp)
+            .count();
+        self.frames.truncate(self.frames.len() - abandoned);
+        let expected = match self.frames.back() {
+            Some(frame) if frame.sp == sp => {
+                self.frames.pop_back().map(|frame| frame.return_address)
+            }
+            _ => None,
+        };
+        if expected != Some(actual) || abandoned > 0 {
+            if self.mismatches.len() == MAX_MISMATCHES {
+                self.mismatches.pop_front();
+            }
+            self.mismatches.push_back(Mismatch {
+                pc,
+                sp,
+                expected,
+                actual,
+                abandoned,
+            });
+            return true;
+        }
+        false
+    }
+
+    /// undo a push or return, given the length before it, the frames it removed,
+    /// the frame a push at the limit dropped and whether it recorded a mismatch
+    pub(crate) fn restore(
+        &mut self,
+        len: usize,
+        removed: &[Frame],
+        dropped: Option<Frame>,
+        mismatch: bool,
+    ) {
+        if let Some(frame) = dropped {
+            // the push left the length unchanged, so take the pushed frame off first
+            self.frames.pop_back();
+            self.frames.push_front(frame);
+        }
+        self.frames.truncate(len.saturating_sub(removed.len()));
+        self.frames.extend(removed);
+        if mismatch {
+            self.mismatches.pop_back();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallStack, Frame, FrameKind, Mismatch};
+
+    fn call(caller: u16, target: u16, sp: u16) -> Frame {
+        Frame {
+            kind: FrameKind::Call,
+            caller,
+            target,
+            return_address: caller + 3,
+            sp,
+        }
+    }
+
+    #[test]
+    fn test_matched_returns() {
+        let mut stack = CallStack::default();
+        stack.push(call(0x0100, 0x0200, 0x23FE));
+        stack.push(call(0x0210, 0x0300, 0x23FC));
+        assert_eq!(stack.active(0x23FC).count(), 2);
+        stack.pop(0x0305, 0x23FC, 0x0213);
+        stack.pop(0x0220, 0x23FE, 0x0103);
+        assert!(stack.frames().is_empty());
+        assert_eq!(stack.mismatches().count(), 0);
+    }
+
+    #[test]
+    fn test_mismatched_returns() {
+        let mut stack = CallStack::default();
+        stack.push(call(0x0100, 0x0200, 0x23FE));
+        stack.push(call(0x0210, 0x0300, 0x23FC));
+        // POP H in the callee drops its return address, then RET skips a level
+        assert_eq!(stack.active(0x23FE).count(), 1);
+        stack.pop(0x0305, 0x23FE, 0x0103);
+        // a pushed address used as a jump
+        stack.pop(0x0400, 0x2400, 0x0500);
+        let mismatches: Vec<&Mismatch> = stack.mismatches().collect();
+        assert_eq!(
+            *mismatches[0],
+            Mismatch {
+                pc: 0x0305,
+                sp: 0x23FE,
+                expected: Some(0x0103),
+                actual: 0x0103,
+                abandoned: 1,
+            }
+        );
+        assert_eq!(mismatches[1].expected, None);
+        assert_eq!(
+            mismatches[1].to_string(),
+            "RET at 0400 returned to 0500, no call pushed SP 2400"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    crt::Image,
+    gif::Gif,
+    machine::Invaders,
+    screen::{self, HEIGHT, WIDTH},
+    sound::{Synth, SAMPLE_RATE},
+    wav::Wav,
+    y4m::Y4m,
+};
+
+/// the overlay's colours, in the order `screen::overlay` is matched against
+const PALETTE: [[u8; 3]; 4] = [[0, 0, 0], screen::WHITE, screen::RED, screen::GREEN];
+
+enum Video {
+    /// every other frame, browsers do not show GIF frames faster than 50 a second
+    Gif(Gif<BufWriter<File>>),
+    Y4m(Y4m<BufWriter<File>>),
+}
+
+/// writes the frames a machine runs, coloured like the overlay, to an animated
+/// GIF or a Y4M video, and what they sound like to a WAV
+pub struct Recorder {
+    video: Option<Video>,
+    audio: Option<(Synth, Wav<BufWriter<File>>)>,
+    scale: usize,
+    frames: u64,
+}
+
+impl Recorder {
+    /// record video to `video`, a .gif or .y4m file, `scale` times the screen's
+    /// size, and sound to `audio`, a .wav file
+    pub fn create(video: Option<&Path>, audio: Option<&Path>, scale: usize) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let (width, height) = (WIDTH * scale, HEIGHT * scale);
+        let video = match video {
+            Some(path) => {
+                let gif = match path.extension().and_then(|e| e.to_str()) {
+                    Some("gif") => true,
+                    Some("y4m") => false,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{}: record to a .gif or .y4m", path.display()),
+                        ))
+                    }
+                };
+                let file = BufWriter::new(File::create(path)?);
+                Some(if gif {
+                    Video::Gif(Gif::new(file, width as u16, height as u16, &PALETTE)?)
+                } else {
+                    Video::Y4m(Y4m::new(file, width, height, 60)?)
+                })
+            }
+            None => None,
+        };
+        let audio = match audio {
+            Some(path) => {
+                let file = BufWriter::new(File::create(path)?);
+                Some((Synth::new(), Wav::new(file, SAMPLE_RATE)?))
+            }
+            None => None,
+        };
+        Ok(Recorder {
+            video,
+            audio,
+            scale,
+            frames: 0,
+        })
+    }
+
+    /// record the frame the machine has just finished
+    pub fn frame(&mut self, machine: &Invaders) -> io::Result<()> {
+        if let Some((synth, wav)) = &mut self.audio {
+            wav.samples(&synth.frame(machine.sound()))?;
+        }
+        let frame = self.frames;
+        self.frames += 1;
+
+        let pixels = || screen::render(machine.video_ram());
+        let (width, height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        match &mut self.video {
+            Some(Video::Gif(gif)) if frame.is_multiple_of(2) => {
+                // delays are in hundredths, 3, 3 and 4 make 30 frames a second
+                let delay = if frame % 6 == 4 { 4 } else { 3 };
+                let scaled = screen::scale(&pixels(), width, height);
+                let indices: Vec<u8> = scaled
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &pixel)| {
+                        if pixel == 0 {
+                            return 0;
+                        }
+                        let (x, y) = (i % width / self.scale, i / width / self.scale);
+                        let colour = screen::overlay(x, y);
+                        PALETTE.iter().position(|&c| c == colour).unwrap() as u8
+                    })
+                    .collect();
+                gif.frame(&indices, delay)
+            }
+            Some(Video::Y4m(y4m)) => {
+                let image = Image::from_screen(&pixels(), true).scale(width, height);
+                y4m.frame(&image.pixels)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// finish off the files
+    pub fn finish(self) -> io::Result<()> {
+        match self.video {
+            Some(Video::Gif(gif)) => drop(gif.finish()?),
+            Some(Video::Y4m(y4m)) => drop(y4m.finish()?),
+            None => {}
+        }
+        if let Some((_, wav)) = self.audio {
+            wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Recorder;
+    use crate::{machine::Invaders, sound::SAMPLES_PER_FRAME};
+
+    #[test]
+    fn test_record() {
+        let rom = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders")).unwrap();
+        let dir = std::env::temp_dir().join(format!("invaders-capture-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (gif, y4m, wav) = (dir.join("a.gif"), dir.join("a.y4m"), dir.join("a.wav"));
+
+        let mut machine = Invaders::new(&rom);
+        let mut recorders = [
+            Recorder::create(Some(&gif), Some(&wav), 2).unwrap(),
+            Recorder::create(Some(&y4m), None, 1).unwrap(),
+        ];
+        for _ in 0..12 {
+            machine.run_frame();
+            for recorder in &mut recorders {
+                recorder.frame(&machine).unwrap();
+            }
+        }
+        for recorder in recorders {
+            recorder.finish().unwrap();
+        }
+        assert!(Recorder::create(Some(&dir.join("a.mp4")), None, 1).is_err());
+        assert!(!dir.join("a.mp4").exists());
+
+        let data = fs::read(&gif).unwrap();
+        assert!(data.starts_with(b"GIF89a\xC0\x01\x00\x02"));
+        assert_eq!(data.last(), Some(&0x3B));
+        // six frames, each with a graphic control extension
+        let frames = data.windows(3).filter(|w| w == b"\x21\xF9\x04").count();
+        assert_eq!(frames, 6);
+
+        let data = fs::read(&y4m).unwrap();
+        let header = b"YUV4MPEG2 W224 H256 F60:1 Ip A1:1 C444\n".len();
+        assert_eq!(data.len(), header + 12 * (6 + 224 * 256 * 3));
+
+        let data = fs::read(&wav).unwrap();
+        assert_eq!(data.len(), 44 + 12 * SAMPLES_PER_FRAME * 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{cpu::Cpu, machine::Machine};
+
+/// where CP/M loads programs and starts them
+pub const TPA: u16 = 0x0100;
+/// programs call the BDOS here, with the function number in C
+pub const BDOS: u16 = 0x0005;
+
+/// just enough of CP/M to run the usual 8080 exerciser programs: the program is
+/// loaded at 0x0100, BDOS functions 2 and 9 print to `output`, and a warm boot,
+/// a jump to 0x0000, ends the run
+pub struct Cpm {
+    pub cpu: Cpu,
+    output: Vec<u8>,
+    error: Option<String>,
+}
+
+impl Cpm {
+    pub fn new(program: &[u8]) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.load(program, TPA);
+        // HLT at the warm boot vector, so a plain cpu stops there too
+        cpu.write_byte(0x0000, 0x76);
+        // the BDOS entry is a RET, the call is handled before it runs, and the word
+        // after it is the top of memory programs put their stack under
+        let top = (cpu.memory().len() - 0x100) as u16;
+        cpu.write_byte(BDOS, 0xC9);
+        cpu.write_word(BDOS + 1, top);
+        // a program may also end by returning to the 0x0000 CP/M pushed
+        cpu.sp = top - 2;
+        cpu.write_word(cpu.sp, 0x0000);
+        cpu.pc = TPA;
+        Cpm {
+            cpu,
+            output: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// everything printed so far
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// why the program was stopped, if a BDOS call it made went wrong
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// true once the program has warm booted
+    pub fn is_finished(&self) -> bool {
+        self.cpu.pc == 0x0000
+    }
+
+    /// run until the program warm boots, a BDOS call fails or `limit` instructions
+    /// have run, returns true if it finished
+    pub fn run(&mut self, limit: u64) -> bool {
+        for _ in 0..limit {
+            if self.is_finished() {
+                return true;
+            }
+            if self.error.is_some() {
+                return false;
+            }
+            self.step();
+        }
+        self.is_finished()
+    }
+
+    fn bdos(&mut self) {
+        match self.cpu.c {
+            // console output of the character in E
+            2 => self.output.push(self.cpu.e),
+            // print the string at DE up to a '$', which must come before the address
+            // wraps back around to DE
+            9 => {
+                let start = self.cpu.de();
+                let text: Vec<u8> = (0..=u16::MAX)
+                    .map(|i| self.cpu.read_byte(start.wrapping_add(i)))
+                    .take_while(|&byte| byte != b'$')
+                    .collect();
+                if text.len() > u16::MAX as usize {
+                    self.error = Some(format!("no '$' ends the string at {:04X}", start));
+                } else {
+                    self.output.extend(text);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Machine for Cpm {
+    fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    fn step(&mut self) {
+        if self.cpu.pc == BDOS {
+            self.bdos();
+        }
+        self.cpu.step();
+    }
+
+    /// the top of memory from the BDOS entry, programs may move their stack though
+    fn stack_base(&self) -> Option<u16> {
+        Some(self.cpu.read_word(BDOS + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cpm;
+
+    #[test]
+    fn test_console_output() {
+        // MVI C,9; LXI D,msg; CALL 5; MVI C,2; MVI E,'!'; CALL 5; JMP 0; msg: "hi$"
+        let program = [
+            0x0E, 0x09, 0x11, 0x13, 0x01, 0xCD, 0x05, 0x00, 0x0E, 0x02, 0x1E, b'!', 0xCD, 0x05,
+            0x00, 0xC3, 0x00, 0x00, 0x00, b'h', b'i', b'$',
+        ];
+        let mut cpm = Cpm::new(&program);
+        // the whole 64 KiB is ram, so the stack goes at the top
+        assert_eq!(cpm.cpu.read_word(0x0006), 0xFF00);
+        assert!(cpm.run(100));
+        assert_eq!(cpm.output(), b"hi!");
+        assert!(cpm.cpu.call_stack.frames().is_empty());
+    }
+
+    #[test]
+    fn test_print_string_without_end() {
+        // MVI C,9; LXI D,0x0200; CALL 5; JMP 0, and no '$' anywhere in memory
+        let program = [
+            0x0E, 0x09, 0x11, 0x00, 0x02, 0xCD, 0x05, 0x00, 0xC3, 0x00, 0x00,
+        ];
+        let mut cpm = Cpm::new(&program);
+        assert!(!cpm.run(100));
+        assert!(!cpm.is_finished());
+        assert_eq!(cpm.error(), Some("no '$' ends the string at 0200"));
+        assert!(cpm.output().is_empty());
+    }
+}
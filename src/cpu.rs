@@ -1,6 +1,15 @@
-use crate::instructions::Instruction;
+use std::{fmt, ops::Range};
 
-const MEMORY_SIZE: usize = 0x4000;
+use crate::{
+    breakpoints::{Breakpoints, Event, Hit},
+    callstack::{CallStack, Frame, FrameKind},
+    history::{Entry, History},
+    instructions::Instruction,
+    savestate::{self, Reader, SaveState, Writer},
+};
+
+/// the 8080 addresses 64 KiB
+pub const ADDRESS_SPACE: usize = 0x10000;
 
 pub struct Cpu {
     pub a: u8,
@@ -10,23 +19,88 @@ pub struct Cpu {
     pub e: u8,
     pub h: u8,
     pub l: u8,
-    pub sp: usize,
-    pub pc: usize,
+    pub sp: u16,
+    pub pc: u16,
+    /// values read by the IN instruction, indexed by port
+    pub ports: [u8; 256],
     memory: Box<[u8]>,
+    /// the part of memory that is rom, instructions cannot write it
+    rom: Range<usize>,
     flags: Flags,
+    interrupts_enabled: bool,
+    halted: bool,
+    cycles: u64,
+    /// the last OUT instruction as (port, value), until taken
+    output: Option<(u8, u8)>,
+    pub breakpoints: Breakpoints,
+    /// the breakpoint that stopped the last instruction, until taken
+    hit: Option<Hit>,
+    /// return addresses pushed by calls, restarts and interrupts
+    pub call_stack: CallStack,
+    /// undo information for stepping backwards, off until given a capacity
+    pub history: History,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub z: bool,  // zero
+    pub s: bool,  // sign
+    pub p: bool,  // parity
+    pub cy: bool, // carry
+    pub ac: bool, // aux carry
+}
+
+impl Flags {
+    /// unpack the flags from a processor status word
+    pub fn from_psw(psw: u8) -> Self {
+        Flags {
+            s: psw & 0x80 != 0,
+            z: psw & 0x40 != 0,
+            ac: psw & 0x10 != 0,
+            p: psw & 0x04 != 0,
+            cy: psw & 0x01 != 0,
+        }
+    }
+}
+
+/// a copy of the cpu registers and flags at a point in time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub flags: Flags,
+    pub interrupts_enabled: bool,
+    pub halted: bool,
+    pub cycles: u64,
 }
 
-#[derive(Clone, Copy)]
-struct Flags {
-    z: bool, // zero
-    s: bool, // sign
-    p: bool, // parity
-    cy: bool, // carry
-             // ac: bool, // aux carry
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
+    /// a cpu with plain ram filling the whole address space
     pub fn new() -> Self {
+        Self::with_memory_size(ADDRESS_SPACE)
+    }
+
+    /// a cpu with `size` bytes of memory, a power of two no larger than the address
+    /// space, addresses past the end are mirrored
+    pub fn with_memory_size(size: usize) -> Self {
+        assert!(
+            size.is_power_of_two() && size <= ADDRESS_SPACE,
+            "memory size must be a power of two up to 64 KiB, got {:#X}",
+            size
+        );
         Cpu {
             a: 0,
             b: 0,
@@ -37,134 +111,1011 @@ impl Cpu {
             l: 0,
             sp: 0,
             pc: 0,
-            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            ports: [0; 256],
+            memory: vec![0; size].into_boxed_slice(),
+            rom: 0..0,
             flags: Flags {
                 z: false,
                 s: false,
                 p: false,
                 cy: false,
-                // ac: false,
+                ac: false,
             },
+            interrupts_enabled: false,
+            halted: false,
+            cycles: 0,
+            output: None,
+            breakpoints: Breakpoints::default(),
+            hit: None,
+            call_stack: CallStack::default(),
+            history: History::default(),
         }
     }
 
-    /// load data into memory at the specified address
-    pub fn load(&mut self, data: &[u8], address: usize) {
-        self.memory[address..address + data.len()].copy_from_slice(data);
+    /// take a snapshot of the registers, flags and counters
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            flags: self.flags,
+            interrupts_enabled: self.interrupts_enabled,
+            halted: self.halted,
+            cycles: self.cycles,
+        }
+    }
+
+    /// restore the registers, flags and counters from a snapshot
+    pub fn set_state(&mut self, state: CpuState) {
+        self.a = state.a;
+        self.b = state.b;
+        self.c = state.c;
+        self.d = state.d;
+        self.e = state.e;
+        self.h = state.h;
+        self.l = state.l;
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.flags = state.flags;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.halted = state.halted;
+        self.cycles = state.cycles;
+    }
+
+    /// the whole memory, without mirroring
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// make `rom` read only to instructions, mirrors included, loading and the
+    /// debuggers still write it through `write_byte`
+    pub fn set_rom(&mut self, rom: Range<usize>) {
+        assert!(
+            rom.end <= self.memory.len(),
+            "rom {:#X}..{:#X} is past the end of memory",
+            rom.start,
+            rom.end
+        );
+        self.rom = rom;
+    }
+
+    /// bytes between the stack pointer and `stack_base`, lowest address first, or
+    /// none if the stack pointer is above `stack_base`
+    pub fn stack(&self, stack_base: u16) -> Option<Vec<u8>> {
+        let len = stack_base.checked_sub(self.sp)?;
+        Some(
+            (0..len)
+                .map(|i| self.read_byte(self.sp.wrapping_add(i)))
+                .collect(),
+        )
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// the flags packed into the processor status word, as pushed by PUSH PSW
+    pub fn psw(&self) -> u8 {
+        (self.flags.s as u8) << 7
+            | (self.flags.z as u8) << 6
+            | (self.flags.ac as u8) << 4
+            | (self.flags.p as u8) << 2
+            | 1 << 1
+            | self.flags.cy as u8
+    }
+
+    /// unpack the flags from a processor status word
+    pub fn set_psw(&mut self, psw: u8) {
+        self.flags = Flags::from_psw(psw);
+    }
+
+    /// total clock cycles executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    /// load data into memory at the specified address, wrapping around the end of the address space
+    pub fn load(&mut self, data: &[u8], address: u16) {
+        for (i, byte) in data.iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u16), *byte);
+        }
     }
 
     /// fetch instruction at the current program counter
     pub fn fetch(&self) -> (Instruction, usize) {
-        Instruction::disassemble(&self.memory, self.pc)
+        self.fetch_at(self.pc)
+    }
+
+    /// decode the instruction at any address without executing it
+    pub fn fetch_at(&self, address: u16) -> (Instruction, usize) {
+        // operands may wrap past 0xFFFF back to 0x0000
+        let bytes = [
+            self.read_byte(address),
+            self.read_byte(address.wrapping_add(1)),
+            self.read_byte(address.wrapping_add(2)),
+        ];
+        Instruction::disassemble(&bytes, 0).expect("three bytes hold any instruction")
+    }
+
+    /// read a byte from memory, addresses above the end of memory are mirrored
+    pub fn read_byte(&self, address: u16) -> u8 {
+        self.memory[address as usize & (self.memory.len() - 1)]
+    }
+
+    /// write a byte to memory, addresses above the end of memory are mirrored
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        let mask = self.memory.len() - 1;
+        self.memory[address as usize & mask] = value;
+    }
+
+    /// read a little endian word from memory
+    pub fn read_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_byte(address),
+            self.read_byte(address.wrapping_add(1)),
+        ])
+    }
+
+    /// write a little endian word to memory
+    pub fn write_word(&mut self, address: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.write_byte(address, lo);
+        self.write_byte(address.wrapping_add(1), hi);
+    }
+
+    /// fetch and execute the instruction at the program counter,
+    /// only counts cycles while halted
+    pub fn step(&mut self) {
+        if self.history.is_enabled() {
+            self.history
+                .begin(self.state(), false, self.call_stack.frames().len());
+        }
+        if self.halted {
+            self.cycles += 4;
+            return;
+        }
+        let (instruction, _) = self.fetch();
+        self.execute(instruction);
     }
 
+    /// request a `RST n` interrupt, returns false if interrupts are disabled
+    pub fn interrupt(&mut self, n: u8) -> bool {
+        if !self.interrupts_enabled {
+            return false;
+        }
+        if self.history.is_enabled() {
+            self.history
+                .begin(self.state(), true, self.call_stack.frames().len());
+        }
+        self.interrupts_enabled = false;
+        self.halted = false;
+        let target = (n as u16 & 0x07) * 8;
+        self.push_stack(self.pc);
+        self.push_frame(Frame {
+            kind: FrameKind::Interrupt,
+            caller: self.pc,
+            target,
+            return_address: self.pc,
+            sp: self.sp,
+        });
+        self.pc = target;
+        self.cycles += 11;
+        if self.breakpoints.watches(Event::Execute) {
+            self.check_breakpoints(Event::Execute, self.pc, None);
+        }
+        true
+    }
 
-    // todo: find a better way to handling incrementing PC when executing instructions
-    // jumps and calls are probably an issue
-    /// execute given instruction and increments the program counter
-    // pub fn execute(&mut self, instruction: Instruction, instr_len: usize) {
+    /// undo the last instruction or interrupt recorded in the history,
+    /// returns what was undone or `None` if the history is empty
+    pub fn step_back(&mut self) -> Option<Entry> {
+        let entry = self.history.pop()?;
+        for &(address, old, _) in entry.writes.iter().rev() {
+            self.write_byte(address, old);
+        }
+        self.call_stack
+            .restore(entry.frames, &entry.removed, entry.dropped, entry.mismatch);
+        self.set_state(entry.state);
+        self.output = None;
+        self.hit = None;
+        Some(entry)
+    }
+
+    /// take the (port, value) written by the last OUT instruction
+    pub fn take_output(&mut self) -> Option<(u8, u8)> {
+        self.output.take()
+    }
+
+    /// take the breakpoint hit since the last call, if any
+    pub fn take_hit(&mut self) -> Option<Hit> {
+        self.hit.take()
+    }
+
+    /// execute given instruction and advance the program counter past it,
+    /// jumps, calls and returns then overwrite the program counter
     pub fn execute(&mut self, instruction: Instruction) {
         use Instruction::*;
+        self.pc = self.pc.wrapping_add(instruction.size() as u16);
+        self.cycles += instruction.cycles();
         match instruction {
-            NOP => {
-                self.pc += 1;
+            NOP => {}
+            LXI_B_D16(d16) => self.set_bc(d16),
+            STAX_B => self.write(self.bc(), self.a),
+            INX_B => self.set_bc(self.bc().wrapping_add(1)),
+            INR_B => self.b = self.inr(self.b),
+            DCR_B => self.b = self.dcr(self.b),
+            MVI_B_D8(d8) => self.b = d8,
+            RLC => {
+                self.flags.cy = self.a & 0x80 != 0;
+                self.a = self.a.rotate_left(1);
+            }
+
+            DAD_B => self.dad(self.bc()),
+            LDAX_B => self.a = self.read(self.bc()),
+            DCX_B => self.set_bc(self.bc().wrapping_sub(1)),
+            INR_C => self.c = self.inr(self.c),
+            DCR_C => self.c = self.dcr(self.c),
+            MVI_C_D8(d8) => self.c = d8,
+            RRC => {
+                self.flags.cy = self.a & 0x01 != 0;
+                self.a = self.a.rotate_right(1);
+            }
+
+            LXI_D_D16(d16) => self.set_de(d16),
+            STAX_D => self.write(self.de(), self.a),
+            INX_D => self.set_de(self.de().wrapping_add(1)),
+            INR_D => self.d = self.inr(self.d),
+            DCR_D => self.d = self.dcr(self.d),
+            MVI_D_D8(d8) => self.d = d8,
+            RAL => {
+                let carry = self.flags.cy as u8;
+                self.flags.cy = self.a & 0x80 != 0;
+                self.a = (self.a << 1) | carry;
+            }
+
+            DAD_D => self.dad(self.de()),
+            LDAX_D => self.a = self.read(self.de()),
+            DCX_D => self.set_de(self.de().wrapping_sub(1)),
+            INR_E => self.e = self.inr(self.e),
+            DCR_E => self.e = self.dcr(self.e),
+            MVI_E_D8(d8) => self.e = d8,
+            RAR => {
+                let carry = self.flags.cy as u8;
+                self.flags.cy = self.a & 0x01 != 0;
+                self.a = (self.a >> 1) | (carry << 7);
+            }
+
+            LXI_H_D16(d16) => self.set_hl(d16),
+            SHLD_ADR(adr) => self.write16(adr, self.hl()),
+            INX_H => self.set_hl(self.hl().wrapping_add(1)),
+            INR_H => self.h = self.inr(self.h),
+            DCR_H => self.h = self.dcr(self.h),
+            MVI_H_D8(d8) => self.h = d8,
+            DAA => self.daa(),
+
+            DAD_H => self.dad(self.hl()),
+            LHLD_ADR(adr) => {
+                let value = self.read16(adr);
+                self.set_hl(value);
+            }
+            DCX_H => self.set_hl(self.hl().wrapping_sub(1)),
+            INR_L => self.l = self.inr(self.l),
+            DCR_L => self.l = self.dcr(self.l),
+            MVI_L_D8(d8) => self.l = d8,
+            CMA => self.a = !self.a,
+
+            LXI_SP_D16(d16) => self.sp = d16,
+            STA_ADR(adr) => self.write(adr, self.a),
+            INX_SP => self.sp = self.sp.wrapping_add(1),
+            INR_M => {
+                let value = self.read(self.hl());
+                let value = self.inr(value);
+                self.write(self.hl(), value);
+            }
+            DCR_M => {
+                let value = self.read(self.hl());
+                let value = self.dcr(value);
+                self.write(self.hl(), value);
             }
+            MVI_M_D8(d8) => self.write(self.hl(), d8),
+            STC => self.flags.cy = true,
+
+            DAD_SP => self.dad(self.sp),
+            LDA_ADR(adr) => self.a = self.read(adr),
+            DCX_SP => self.sp = self.sp.wrapping_sub(1),
+            INR_A => self.a = self.inr(self.a),
+            DCR_A => self.a = self.dcr(self.a),
+            MVI_A_D8(d8) => self.a = d8,
+            CMC => self.flags.cy = !self.flags.cy,
+
+            MOV_B_B => {}
+            MOV_B_C => self.b = self.c,
+            MOV_B_D => self.b = self.d,
+            MOV_B_E => self.b = self.e,
+            MOV_B_H => self.b = self.h,
+            MOV_B_L => self.b = self.l,
+            MOV_B_M => self.b = self.read(self.hl()),
+            MOV_B_A => self.b = self.a,
+
+            MOV_C_B => self.c = self.b,
+            MOV_C_C => {}
+            MOV_C_D => self.c = self.d,
+            MOV_C_E => self.c = self.e,
+            MOV_C_H => self.c = self.h,
+            MOV_C_L => self.c = self.l,
+            MOV_C_M => self.c = self.read(self.hl()),
+            MOV_C_A => self.c = self.a,
+
+            MOV_D_B => self.d = self.b,
+            MOV_D_C => self.d = self.c,
+            MOV_D_D => {}
+            MOV_D_E => self.d = self.e,
+            MOV_D_H => self.d = self.h,
+            MOV_D_L => self.d = self.l,
+            MOV_D_M => self.d = self.read(self.hl()),
+            MOV_D_A => self.d = self.a,
+
+            MOV_E_B => self.e = self.b,
+            MOV_E_C => self.e = self.c,
+            MOV_E_D => self.e = self.d,
+            MOV_E_E => {}
+            MOV_E_H => self.e = self.h,
+            MOV_E_L => self.e = self.l,
+            MOV_E_M => self.e = self.read(self.hl()),
+            MOV_E_A => self.e = self.a,
+
+            MOV_H_B => self.h = self.b,
+            MOV_H_C => self.h = self.c,
+            MOV_H_D => self.h = self.d,
+            MOV_H_E => self.h = self.e,
+            MOV_H_H => {}
+            MOV_H_L => self.h = self.l,
+            MOV_H_M => self.h = self.read(self.hl()),
+            MOV_H_A => self.h = self.a,
+
+            MOV_L_B => self.l = self.b,
+            MOV_L_C => self.l = self.c,
+            MOV_L_D => self.l = self.d,
+            MOV_L_E => self.l = self.e,
+            MOV_L_H => self.l = self.h,
+            MOV_L_L => {}
+            MOV_L_M => self.l = self.read(self.hl()),
+            MOV_L_A => self.l = self.a,
+
+            MOV_M_B => self.write(self.hl(), self.b),
+            MOV_M_C => self.write(self.hl(), self.c),
+            MOV_M_D => self.write(self.hl(), self.d),
+            MOV_M_E => self.write(self.hl(), self.e),
+            MOV_M_H => self.write(self.hl(), self.h),
+            MOV_M_L => self.write(self.hl(), self.l),
+            HLT => self.halted = true,
+            MOV_M_A => self.write(self.hl(), self.a),
+
+            MOV_A_B => self.a = self.b,
+            MOV_A_C => self.a = self.c,
+            MOV_A_D => self.a = self.d,
+            MOV_A_E => self.a = self.e,
+            MOV_A_H => self.a = self.h,
+            MOV_A_L => self.a = self.l,
+            MOV_A_M => self.a = self.read(self.hl()),
+            MOV_A_A => {}
+
+            ADD_B => self.add(self.b, false),
+            ADD_C => self.add(self.c, false),
+            ADD_D => self.add(self.d, false),
+            ADD_E => self.add(self.e, false),
+            ADD_H => self.add(self.h, false),
+            ADD_L => self.add(self.l, false),
             ADD_M => {
-                let addr = (((self.h as u16) << 8) | (self.h as u16)) as usize;
-                let res = (self.a as u16) + (self.memory[addr] as u16);
-                self.flags = Flags::get(res);
-                self.a = (res & 0xFF) as u8;
-                self.pc += 1;
+                let value = self.read(self.hl());
+                self.add(value, false);
+            }
+            ADD_A => self.add(self.a, false),
+
+            ADC_B => self.add(self.b, self.flags.cy),
+            ADC_C => self.add(self.c, self.flags.cy),
+            ADC_D => self.add(self.d, self.flags.cy),
+            ADC_E => self.add(self.e, self.flags.cy),
+            ADC_H => self.add(self.h, self.flags.cy),
+            ADC_L => self.add(self.l, self.flags.cy),
+            ADC_M => {
+                let value = self.read(self.hl());
+                self.add(value, self.flags.cy);
+            }
+            ADC_A => self.add(self.a, self.flags.cy),
+
+            SUB_B => self.a = self.sub(self.b, false),
+            SUB_C => self.a = self.sub(self.c, false),
+            SUB_D => self.a = self.sub(self.d, false),
+            SUB_E => self.a = self.sub(self.e, false),
+            SUB_H => self.a = self.sub(self.h, false),
+            SUB_L => self.a = self.sub(self.l, false),
+            SUB_M => {
+                let value = self.read(self.hl());
+                self.a = self.sub(value, false);
+            }
+            SUB_A => self.a = self.sub(self.a, false),
+
+            SBB_B => self.a = self.sub(self.b, self.flags.cy),
+            SBB_C => self.a = self.sub(self.c, self.flags.cy),
+            SBB_D => self.a = self.sub(self.d, self.flags.cy),
+            SBB_E => self.a = self.sub(self.e, self.flags.cy),
+            SBB_H => self.a = self.sub(self.h, self.flags.cy),
+            SBB_L => self.a = self.sub(self.l, self.flags.cy),
+            SBB_M => {
+                let value = self.read(self.hl());
+                self.a = self.sub(value, self.flags.cy);
+            }
+            SBB_A => self.a = self.sub(self.a, self.flags.cy),
+
+            ANA_B => self.ana(self.b),
+            ANA_C => self.ana(self.c),
+            ANA_D => self.ana(self.d),
+            ANA_E => self.ana(self.e),
+            ANA_H => self.ana(self.h),
+            ANA_L => self.ana(self.l),
+            ANA_M => {
+                let value = self.read(self.hl());
+                self.ana(value);
+            }
+            ANA_A => self.ana(self.a),
+
+            XRA_B => self.xra(self.b),
+            XRA_C => self.xra(self.c),
+            XRA_D => self.xra(self.d),
+            XRA_E => self.xra(self.e),
+            XRA_H => self.xra(self.h),
+            XRA_L => self.xra(self.l),
+            XRA_M => {
+                let value = self.read(self.hl());
+                self.xra(value);
+            }
+            XRA_A => self.xra(self.a),
+
+            ORA_B => self.ora(self.b),
+            ORA_C => self.ora(self.c),
+            ORA_D => self.ora(self.d),
+            ORA_E => self.ora(self.e),
+            ORA_H => self.ora(self.h),
+            ORA_L => self.ora(self.l),
+            ORA_M => {
+                let value = self.read(self.hl());
+                self.ora(value);
+            }
+            ORA_A => self.ora(self.a),
+
+            CMP_B => {
+                self.sub(self.b, false);
+            }
+            CMP_C => {
+                self.sub(self.c, false);
             }
-            ADI_D8(d8) => {
-                let res = (self.a as u16) + (d8 as u16);
-                self.flags = Flags::get(res);
-                self.a = (res & 0xFF) as u8;
-                self.pc += 2;
+            CMP_D => {
+                self.sub(self.d, false);
             }
-            CALL_ADR(adr) => {
-                self._push_stack(self.pc as u16);
-                self.pc = adr as usize;
+            CMP_E => {
+                self.sub(self.e, false);
             }
-            JMP_ADR(adr) => {
-                self.pc = adr as usize;
+            CMP_H => {
+                self.sub(self.h, false);
             }
-            LXI_SP_D16(d16) => {
-                self.sp = d16 as usize;
-                self.pc += 3;
+            CMP_L => {
+                self.sub(self.l, false);
             }
-            MVI_B_D8(d8) => {
-                self.b = d8;
-                self.pc += 2;
+            CMP_M => {
+                let value = self.read(self.hl());
+                self.sub(value, false);
             }
-            _ => panic!("unimplemented instruction: {:X?}", instruction),
+            CMP_A => {
+                self.sub(self.a, false);
+            }
+
+            RNZ => self.return_if(!self.flags.z),
+            POP_B => {
+                let value = self.pop_stack();
+                self.set_bc(value);
+            }
+            JNZ_ADR(adr) => self.jump_if(!self.flags.z, adr),
+            JMP_ADR(adr) => self.pc = adr,
+            CNZ_ADR(adr) => self.call_if(!self.flags.z, adr),
+            PUSH_B => self.push_stack(self.bc()),
+            ADI_D8(d8) => self.add(d8, false),
+            RST_0 => self.restart(0x00),
+            RZ => self.return_if(self.flags.z),
+            RET => self.ret(),
+            JZ_ADR(adr) => self.jump_if(self.flags.z, adr),
+
+            CZ_ADR(adr) => self.call_if(self.flags.z, adr),
+            CALL_ADR(adr) => self.call(adr),
+            ACI_D8(d8) => self.add(d8, self.flags.cy),
+            RST_1 => self.restart(0x08),
+            RNC => self.return_if(!self.flags.cy),
+            POP_D => {
+                let value = self.pop_stack();
+                self.set_de(value);
+            }
+            JNC_ADR(adr) => self.jump_if(!self.flags.cy, adr),
+            OUT_D8(port) => {
+                if self.breakpoints.watches(Event::Out) {
+                    self.check_breakpoints(Event::Out, port as u16, Some(self.a));
+                }
+                self.output = Some((port, self.a));
+            }
+            CNC_ADR(adr) => self.call_if(!self.flags.cy, adr),
+            PUSH_D => self.push_stack(self.de()),
+            SUI_D8(d8) => self.a = self.sub(d8, false),
+            RST_2 => self.restart(0x10),
+            RC => self.return_if(self.flags.cy),
+
+            JC_ADR(adr) => self.jump_if(self.flags.cy, adr),
+            IN_D8(port) => {
+                self.a = self.ports[port as usize];
+                if self.breakpoints.watches(Event::In) {
+                    self.check_breakpoints(Event::In, port as u16, Some(self.a));
+                }
+            }
+            CC_ADR(adr) => self.call_if(self.flags.cy, adr),
+
+            SBI_D8(d8) => self.a = self.sub(d8, self.flags.cy),
+            RST_3 => self.restart(0x18),
+            RPO => self.return_if(!self.flags.p),
+            POP_H => {
+                let value = self.pop_stack();
+                self.set_hl(value);
+            }
+            JPO_ADR(adr) => self.jump_if(!self.flags.p, adr),
+            XTHL => {
+                let value = self.read16(self.sp);
+                self.write16(self.sp, self.hl());
+                self.set_hl(value);
+            }
+            CPO_ADR(adr) => self.call_if(!self.flags.p, adr),
+            PUSH_H => self.push_stack(self.hl()),
+            ANI_D8(d8) => self.ana(d8),
+            RST_4 => self.restart(0x20),
+            RPE => self.return_if(self.flags.p),
+            PCHL => self.pc = self.hl(),
+            JPE_ADR(adr) => self.jump_if(self.flags.p, adr),
+            XCHG => {
+                std::mem::swap(&mut self.d, &mut self.h);
+                std::mem::swap(&mut self.e, &mut self.l);
+            }
+            CPE_ADR(adr) => self.call_if(self.flags.p, adr),
+
+            XRI_D8(d8) => self.xra(d8),
+            RST_5 => self.restart(0x28),
+            RP => self.return_if(!self.flags.s),
+            POP_PSW => {
+                let [psw, a] = self.pop_stack().to_le_bytes();
+                self.a = a;
+                self.set_psw(psw);
+            }
+            JP_ADR(adr) => self.jump_if(!self.flags.s, adr),
+            DI => self.interrupts_enabled = false,
+            CP_ADR(adr) => self.call_if(!self.flags.s, adr),
+            PUSH_PSW => self.push_stack(u16::from_le_bytes([self.psw(), self.a])),
+            ORI_D8(d8) => self.ora(d8),
+            RST_6 => self.restart(0x30),
+            RM => self.return_if(self.flags.s),
+            SPHL => self.sp = self.hl(),
+            JM_ADR(adr) => self.jump_if(self.flags.s, adr),
+            EI => self.interrupts_enabled = true,
+            CM_ADR(adr) => self.call_if(self.flags.s, adr),
+
+            CPI_D8(d8) => {
+                self.sub(d8, false);
+            }
+            RST_7 => self.restart(0x38),
+        }
+        if self.breakpoints.watches(Event::Execute) {
+            self.check_breakpoints(Event::Execute, self.pc, None);
         }
     }
 
     pub fn print_state(&self) {
-        println!("CPU State:");
-        println!("PC = {:#06X}, SP = {:#06X}", self.pc, self.sp);
-        println!("  Registers:");
-        println!("    a = {:#04X}, b = {:#04X}, c = {:#04X}, d = {:#04X}", self.a, self.b, self.c, self.d);
-        println!("    e = {:#04X}, h = {:#04X}, l = {:#04X}", self.e , self.h, self.l);
-        println!("  Flags:");
-        println!("    z = {}, s = {}", self.flags.z, self.flags.s);
-        println!("    p = {}, cy = {}\n", self.flags.p, self.flags.cy);
-    }
-
-    pub fn print_stack(&self, stack_base: usize) {
-        if self.sp != 0 {
-            let slice = &self.memory[self.sp..stack_base];
-            println!("Stack:\n{:02X?}", slice);
-        } else {
+        println!("{}\n", self.state());
+    }
+
+    pub fn print_stack(&self, stack_base: u16) {
+        if self.sp == 0 {
             println!("Stack pointer not set");
+            return;
+        }
+        match self.stack(stack_base) {
+            Some(stack) => println!("Stack:\n{:02X?}", stack),
+            None => println!("Stack pointer above {:04X}", stack_base),
         }
     }
 
-    fn _push_stack(&mut self, value: u16) {
-        let bytes = value.to_le_bytes();
-        self.memory[self.sp - 2..self.sp].copy_from_slice(&bytes);
-        self.sp -= 2;
+    fn push_stack(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write16(self.sp, value);
     }
 
-    fn _pop_stack(&mut self) -> u16 {
-        let value = ((self.memory[self.sp + 1] as u16) << 8) | (self.memory[self.sp] as u16);
-        self.sp += 2;
+    fn pop_stack(&mut self) -> u16 {
+        let value = self.read16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
         value
     }
-}
 
-impl Flags {
-    fn get(result: u16) -> Self {
-        Flags {
-            z: (result & 0xFF) == 0,
-            s: (result & 0x80) != 0,
-            p: parity(result as u8),
-            cy: result > 0xFF,
+    /// read a byte on behalf of an instruction, checking read breakpoints
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.read_byte(address);
+        if self.breakpoints.watches(Event::Read) {
+            self.check_breakpoints(Event::Read, address, Some(value));
+        }
+        value
+    }
+
+    /// write a byte on behalf of an instruction, checking write breakpoints, writes
+    /// to rom are dropped
+    fn write(&mut self, address: u16, value: u8) {
+        let writable = !self
+            .rom
+            .contains(&(address as usize & (self.memory.len() - 1)));
+        if writable && self.history.is_enabled() {
+            let old = self.read_byte(address);
+            if let Some(entry) = self.history.current() {
+                entry.writes.push((address, old, value));
+            }
+        }
+        if writable {
+            self.write_byte(address, value);
+        }
+        if self.breakpoints.watches(Event::Write) {
+            self.check_breakpoints(Event::Write, address, Some(value));
+        }
+    }
+
+    fn read16(&mut self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    fn write16(&mut self, address: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.write(address, lo);
+        self.write(address.wrapping_add(1), hi);
+    }
+
+    fn check_breakpoints(&mut self, event: Event, address: u16, value: Option<u8>) {
+        for index in self.breakpoints.matching(self, event, address, value) {
+            if let Some(id) = self.breakpoints.hit(index) {
+                self.hit.get_or_insert(Hit {
+                    id,
+                    event,
+                    address,
+                    value,
+                });
+            }
+        }
+    }
+
+    /// push the return address, which the program counter already points at, and jump
+    fn call(&mut self, address: u16) {
+        self.enter(FrameKind::Call, self.pc.wrapping_sub(3), address);
+    }
+
+    fn restart(&mut self, address: u16) {
+        self.enter(FrameKind::Restart, self.pc.wrapping_sub(1), address);
+    }
+
+    fn enter(&mut self, kind: FrameKind, caller: u16, target: u16) {
+        self.push_stack(self.pc);
+        self.push_frame(Frame {
+            kind,
+            caller,
+            target,
+            return_address: self.pc,
+            sp: self.sp,
+        });
+        self.pc = target;
+    }
+
+    /// push onto the shadow call stack, keeping any frame dropped at the limit in
+    /// the history so stepping back can put it back
+    fn push_frame(&mut self, frame: Frame) {
+        let dropped = self.call_stack.push(frame);
+        if self.history.is_enabled() {
+            if let Some(entry) = self.history.current() {
+                entry.dropped = dropped;
+            }
+        }
+    }
+
+    fn ret(&mut self) {
+        let sp = self.sp;
+        let address = self.pop_stack();
+        if self.history.is_enabled() {
+            let removed = self.call_stack.removed_by_return(sp);
+            let mismatch = self.call_stack.pop(self.pc.wrapping_sub(1), sp, address);
+            if let Some(entry) = self.history.current() {
+                entry.removed = removed;
+                entry.mismatch = mismatch;
+            }
+        } else {
+            self.call_stack.pop(self.pc.wrapping_sub(1), sp, address);
+        }
+        self.pc = address;
+    }
+
+    fn call_if(&mut self, condition: bool, address: u16) {
+        if condition {
+            self.cycles += 6;
+            self.call(address);
+        }
+    }
+
+    fn return_if(&mut self, condition: bool) {
+        if condition {
+            self.cycles += 6;
+            self.ret();
+        }
+    }
+
+    fn jump_if(&mut self, condition: bool, address: u16) {
+        if condition {
+            self.pc = address;
+        }
+    }
+
+    /// set the zero, sign and parity flags from a result
+    fn set_zsp(&mut self, value: u8) {
+        self.flags.z = value == 0;
+        self.flags.s = value & 0x80 != 0;
+        self.flags.p = parity(value);
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let carry = carry as u16;
+        let res = (self.a as u16) + (value as u16) + carry;
+        self.flags.ac = ((self.a & 0x0F) as u16) + ((value & 0x0F) as u16) + carry > 0x0F;
+        self.flags.cy = res > 0xFF;
+        self.a = (res & 0xFF) as u8;
+        self.set_zsp(self.a);
+    }
+
+    /// subtract from the accumulator and set flags, the result is returned so CMP can discard it
+    fn sub(&mut self, value: u8, borrow: bool) -> u8 {
+        let borrow = borrow as u16;
+        let res = (self.a as u16)
+            .wrapping_sub(value as u16)
+            .wrapping_sub(borrow);
+        // the 8080 adds the two's complement, so aux carry is the carry out of that addition
+        self.flags.ac = ((self.a & 0x0F) as u16) + ((!value & 0x0F) as u16) + (1 - borrow) > 0x0F;
+        self.flags.cy = (value as u16) + borrow > self.a as u16;
+        let res = (res & 0xFF) as u8;
+        self.set_zsp(res);
+        res
+    }
+
+    fn ana(&mut self, value: u8) {
+        // the 8080 sets aux carry from bit 3 of the operands
+        self.flags.ac = (self.a | value) & 0x08 != 0;
+        self.flags.cy = false;
+        self.a &= value;
+        self.set_zsp(self.a);
+    }
+
+    fn xra(&mut self, value: u8) {
+        self.flags.ac = false;
+        self.flags.cy = false;
+        self.a ^= value;
+        self.set_zsp(self.a);
+    }
+
+    fn ora(&mut self, value: u8) {
+        self.flags.ac = false;
+        self.flags.cy = false;
+        self.a |= value;
+        self.set_zsp(self.a);
+    }
+
+    fn inr(&mut self, value: u8) -> u8 {
+        let res = value.wrapping_add(1);
+        self.flags.ac = res & 0x0F == 0;
+        self.set_zsp(res);
+        res
+    }
+
+    fn dcr(&mut self, value: u8) -> u8 {
+        let res = value.wrapping_sub(1);
+        self.flags.ac = res & 0x0F != 0x0F;
+        self.set_zsp(res);
+        res
+    }
+
+    fn dad(&mut self, value: u16) {
+        let res = (self.hl() as u32) + (value as u32);
+        self.flags.cy = res > 0xFFFF;
+        self.set_hl((res & 0xFFFF) as u16);
+    }
+
+    fn daa(&mut self) {
+        let mut correction = 0;
+        let mut carry = self.flags.cy;
+        let lsb = self.a & 0x0F;
+        let msb = self.a >> 4;
+        if self.flags.ac || lsb > 9 {
+            correction += 0x06;
+        }
+        if self.flags.cy || msb > 9 || (msb >= 9 && lsb > 9) {
+            correction += 0x60;
+            carry = true;
         }
+        self.add(correction, false);
+        self.flags.cy = carry;
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "CPU State:")?;
+        writeln!(f, "PC = {:#06X}, SP = {:#06X}", self.pc, self.sp)?;
+        writeln!(f, "  Registers:")?;
+        writeln!(
+            f,
+            "    a = {:#04X}, b = {:#04X}, c = {:#04X}, d = {:#04X}",
+            self.a, self.b, self.c, self.d
+        )?;
+        writeln!(
+            f,
+            "    e = {:#04X}, h = {:#04X}, l = {:#04X}",
+            self.e, self.h, self.l
+        )?;
+        writeln!(f, "  Flags:")?;
+        writeln!(
+            f,
+            "    z = {}, s = {}, ac = {}",
+            self.flags.z, self.flags.s, self.flags.ac
+        )?;
+        writeln!(f, "    p = {}, cy = {}", self.flags.p, self.flags.cy)?;
+        write!(
+            f,
+            "  Interrupts enabled = {}, Halted = {}, Cycles = {}",
+            self.interrupts_enabled, self.halted, self.cycles
+        )
     }
 }
 
 fn parity(n: u8) -> bool {
-    n.count_ones() % 2 == 0
+    n.count_ones().is_multiple_of(2)
 }
 
 // fn parity_u16(n: u16) -> bool {
 //     n.count_ones() % 2 == 0
 // }
 
+/// a cpu read from a save state, checked but not applied yet
+pub(crate) struct SavedCpu {
+    state: CpuState,
+    memory: Vec<u8>,
+    ports: [u8; 256],
+}
+
+impl Cpu {
+    pub(crate) fn read_saved(&self, reader: &mut Reader) -> Result<SavedCpu, savestate::Error> {
+        let mut state = CpuState {
+            a: reader.u8()?,
+            b: reader.u8()?,
+            c: reader.u8()?,
+            d: reader.u8()?,
+            e: reader.u8()?,
+            h: reader.u8()?,
+            l: reader.u8()?,
+            ..CpuState::default()
+        };
+        state.flags = Flags::from_psw(reader.u8()?);
+        state.sp = reader.u16()?;
+        state.pc = reader.u16()?;
+        state.interrupts_enabled = reader.bool()?;
+        state.halted = reader.bool()?;
+        state.cycles = reader.u64()?;
+        let len = reader.u32()? as usize;
+        if len != self.memory.len() {
+            return Err(savestate::Error::Invalid("memory size differs"));
+        }
+        let memory = reader.bytes(len)?.to_vec();
+        let mut ports = [0; 256];
+        ports.copy_from_slice(reader.bytes(256)?);
+        Ok(SavedCpu {
+            state,
+            memory,
+            ports,
+        })
+    }
+
+    /// restore a saved cpu, the history, call stack and pending output or
+    /// breakpoint hit no longer apply and are cleared, breakpoints are kept
+    pub(crate) fn apply_saved(&mut self, saved: SavedCpu) {
+        self.set_state(saved.state);
+        self.memory.copy_from_slice(&saved.memory);
+        self.ports = saved.ports;
+        self.output = None;
+        self.hit = None;
+        self.call_stack.clear();
+        self.history.clear();
+    }
+}
+
+impl SaveState for Cpu {
+    const KIND: [u8; 4] = *b"CPU ";
+
+    fn save(&self, writer: &mut Writer) {
+        for register in [self.a, self.b, self.c, self.d, self.e, self.h, self.l] {
+            writer.u8(register);
+        }
+        writer.u8(self.psw());
+        writer.u16(self.sp);
+        writer.u16(self.pc);
+        writer.bool(self.interrupts_enabled);
+        writer.bool(self.halted);
+        writer.u64(self.cycles);
+        writer.u32(self.memory.len() as u32);
+        writer.bytes(&self.memory);
+        writer.bytes(&self.ports);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), savestate::Error> {
+        let saved = self.read_saved(reader)?;
+        self.apply_saved(saved);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::parity;
+    use super::{parity, Cpu, CpuState, Flags};
+    use crate::{
+        instructions::Instruction,
+        savestate::{Error, SaveState},
+    };
 
     #[test]
     fn test_parity() {
         let odd = 0b00000001;
-        assert_eq!(parity(odd), false);
+        assert!(!parity(odd));
         let odd = 0b00010000;
-        assert_eq!(parity(odd), false);
+        assert!(!parity(odd));
         let odd = 0b01010111;
-        assert_eq!(parity(odd), false);
+        assert!(!parity(odd));
 
         // let odd = 0b0000000000000001;
         // assert_eq!(parity_u16(odd), false);
@@ -174,11 +1125,11 @@ mod tests {
         // assert_eq!(parity_u16(odd), false);
 
         let even = 0b00000011;
-        assert_eq!(parity(even), true);
+        assert!(parity(even));
         let even = 0b11000000;
-        assert_eq!(parity(even), true);
+        assert!(parity(even));
         let even = 0b11001111;
-        assert_eq!(parity(even), true);
+        assert!(parity(even));
 
         // let even = 0b0000000000000011;
         // assert_eq!(parity_u16(even), true);
@@ -187,4 +1138,227 @@ mod tests {
         // let even = 0b0000100110101001;
         // assert_eq!(parity_u16(even), true);
     }
+
+    #[test]
+    fn test_push_wraps_below_zero() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x0000;
+        cpu.push_stack(0xBEEF);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.read_byte(0xFFFE), 0xEF);
+        assert_eq!(cpu.read_byte(0xFFFF), 0xBE);
+        assert_eq!(cpu.pop_stack(), 0xBEEF);
+        assert_eq!(cpu.sp, 0x0000);
+    }
+
+    #[test]
+    fn test_push_straddles_top_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x0001;
+        cpu.push_stack(0x1234);
+        assert_eq!(cpu.sp, 0xFFFF);
+        assert_eq!(cpu.read_byte(0xFFFF), 0x34);
+        assert_eq!(cpu.read_byte(0x0000), 0x12);
+        assert_eq!(cpu.pop_stack(), 0x1234);
+        assert_eq!(cpu.sp, 0x0001);
+    }
+
+    #[test]
+    fn test_memory_is_mirrored() {
+        let mut cpu = Cpu::with_memory_size(0x4000);
+        cpu.write_byte(0x4000, 0xAA);
+        assert_eq!(cpu.read_byte(0x0000), 0xAA);
+        assert_eq!(cpu.read_byte(0xC000), 0xAA);
+        assert_eq!(cpu.memory().len(), 0x4000);
+
+        // the full address space is not mirrored
+        let mut cpu = Cpu::new();
+        cpu.write_byte(0x4000, 0xAA);
+        assert_eq!(cpu.read_byte(0x0000), 0x00);
+        assert_eq!(cpu.read_byte(0x4000), 0xAA);
+        assert_eq!(cpu.memory().len(), 0x10000);
+    }
+
+    #[test]
+    fn test_rom_is_read_only() {
+        let mut cpu = Cpu::with_memory_size(0x4000);
+        cpu.set_rom(0x0000..0x2000);
+        // MVI A,0x55; STA 0x1000; STA 0x5000 (a mirror of 0x1000); STA 0x2000
+        cpu.load(
+            &[
+                0x3E, 0x55, 0x32, 0x00, 0x10, 0x32, 0x00, 0x50, 0x32, 0x00, 0x20,
+            ],
+            0,
+        );
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.read_byte(0x1000), 0x00);
+        assert_eq!(cpu.read_byte(0x2000), 0x55);
+        // the program itself went in, as would a debugger's patch
+        assert_eq!(cpu.read_byte(0x0000), 0x3E);
+        cpu.write_byte(0x1000, 0xAA);
+        assert_eq!(cpu.read_byte(0x1000), 0xAA);
+    }
+
+    #[test]
+    fn test_fetch_wraps_operands() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0xC3, 0x34, 0x12], 0xFFFF);
+        cpu.pc = 0xFFFF;
+        let (inst, len) = cpu.fetch();
+        assert!(matches!(inst, Instruction::JMP_ADR(0x1234)));
+        assert_eq!(len, 3);
+    }
+
+    #[test]
+    fn test_pc_wraps_past_top() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xFFFF;
+        cpu.execute(Instruction::NOP);
+        assert_eq!(cpu.pc, 0x0000);
+
+        cpu.pc = 0xFFFE;
+        cpu.execute(Instruction::MVI_B_D8(0x42));
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.b, 0x42);
+    }
+
+    #[test]
+    fn test_call_pushes_wrapped_return_address() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xFFFE;
+        cpu.sp = 0x2400;
+        cpu.execute(Instruction::CALL_ADR(0x0100));
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(cpu.pop_stack(), 0x0001);
+    }
+
+    #[test]
+    fn test_state_snapshot() {
+        let mut cpu = Cpu::new();
+        cpu.execute(Instruction::LXI_SP_D16(0x2400));
+        cpu.execute(Instruction::MVI_B_D8(0x10));
+        cpu.execute(Instruction::ADI_D8(0x80));
+        cpu.execute(Instruction::EI);
+
+        let expected = CpuState {
+            a: 0x80,
+            b: 0x10,
+            sp: 0x2400,
+            pc: 0x0008,
+            flags: Flags {
+                s: true,
+                ..Flags::default()
+            },
+            interrupts_enabled: true,
+            cycles: 28,
+            ..CpuState::default()
+        };
+        assert_eq!(cpu.state(), expected);
+        assert_eq!(cpu.state(), cpu.state());
+    }
+
+    #[test]
+    fn test_stack_contents() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x2400;
+        cpu.push_stack(0x1234);
+        cpu.push_stack(0xABCD);
+        assert_eq!(cpu.stack(0x2400), Some(vec![0xCD, 0xAB, 0x34, 0x12]));
+        assert_eq!(cpu.stack(0x23FC), Some(vec![]));
+        // not the whole address space less a few bytes
+        assert_eq!(cpu.stack(0x2000), None);
+    }
+
+    #[test]
+    fn test_push_pop_psw() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x2400;
+        cpu.a = 0xFF;
+        cpu.execute(Instruction::ADI_D8(0x01));
+        assert_eq!(cpu.psw(), 0b0101_0111);
+        cpu.execute(Instruction::PUSH_PSW);
+        assert_eq!(cpu.read_word(0x23FE), 0x0057);
+        cpu.execute(Instruction::XRA_A);
+        cpu.execute(Instruction::MVI_A_D8(0x12));
+        cpu.execute(Instruction::POP_PSW);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.psw(), 0b0101_0111);
+    }
+
+    #[test]
+    fn test_conditional_call_cycles() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x2400;
+        cpu.execute(Instruction::CNZ_ADR(0x1000));
+        assert_eq!(cpu.pc, 0x1000);
+        assert_eq!(cpu.cycles(), 17);
+        cpu.execute(Instruction::XRA_A);
+        cpu.execute(Instruction::CNZ_ADR(0x2000));
+        assert_eq!(cpu.pc, 0x1004);
+        assert_eq!(cpu.cycles(), 17 + 4 + 11);
+        cpu.execute(Instruction::RZ);
+        assert_eq!(cpu.pc, 0x0003);
+        assert_eq!(cpu.cycles(), 17 + 4 + 11 + 11);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x2400;
+        cpu.pc = 0x0ADA;
+        assert!(!cpu.interrupt(2));
+        cpu.execute(Instruction::EI);
+        cpu.execute(Instruction::HLT);
+        assert!(cpu.is_halted());
+        assert!(cpu.interrupt(2));
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0010);
+        assert_eq!(cpu.read_word(0x23FE), 0x0ADC);
+        assert!(!cpu.state().interrupts_enabled);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut cpu = Cpu::new();
+        // LXI SP,0x2400; MVI A,0x81; ADI 0x81; PUSH PSW; EI; HLT
+        cpu.load(
+            &[0x31, 0x00, 0x24, 0x3E, 0x81, 0xC6, 0x81, 0xF5, 0xFB, 0x76],
+            0,
+        );
+        cpu.ports[1] = 0x08;
+        for _ in 0..6 {
+            cpu.step();
+        }
+        let data = cpu.save_state();
+
+        let mut loaded = Cpu::new();
+        loaded.load_state(&data).unwrap();
+        assert_eq!(loaded.state(), cpu.state());
+        assert_eq!(loaded.memory(), cpu.memory());
+        assert_eq!(loaded.ports, cpu.ports);
+        assert!(loaded.is_halted());
+
+        let mut corrupt = data.clone();
+        corrupt[30] ^= 1;
+        assert!(matches!(
+            loaded.load_state(&corrupt),
+            Err(Error::Checksum { .. })
+        ));
+        assert!(matches!(
+            loaded.load_state(&data[..40]),
+            Err(Error::Truncated)
+        ));
+        let mut newer = data.clone();
+        newer[8] = 99;
+        assert!(matches!(
+            loaded.load_state(&newer),
+            Err(Error::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            loaded.load_state(b"not a save state at all"),
+            Err(Error::BadMagic)
+        ));
+    }
 }
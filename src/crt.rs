@@ -0,0 +1,390 @@
+use std::str::FromStr;
+
+use crate::screen::{self, HEIGHT, WIDTH};
+
+/// an RGB picture, three bytes a pixel, row by row from the top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// an all black picture
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// a picture from `screen::render`, white or tinted like the overlay
+    pub fn from_screen(pixels: &[u8], overlay: bool) -> Self {
+        let mut image = Image::new(WIDTH, HEIGHT);
+        for (i, &pixel) in pixels.iter().enumerate() {
+            let colour = if overlay {
+                screen::overlay(i % WIDTH, i / WIDTH)
+            } else {
+                screen::WHITE
+            };
+            for (out, channel) in image.pixels[i * 3..i * 3 + 3].iter_mut().zip(colour) {
+                *out = (pixel as u16 * channel as u16 / 0xFF) as u8;
+            }
+        }
+        image
+    }
+
+    /// stretched to `width` by `height`, each pixel taken from the source pixel
+    /// under its centre
+    pub fn scale(&self, width: usize, height: usize) -> Self {
+        let columns: Vec<usize> = (0..width)
+            .map(|x| (2 * x + 1) * self.width / (2 * width))
+            .collect();
+        let row_bytes = width * 3;
+        let mut scaled = Vec::with_capacity(row_bytes * height);
+        let mut last = None;
+        for y in 0..height {
+            let row = (2 * y + 1) * self.height / (2 * height);
+            if last == Some(row) {
+                // the same as the row above
+                scaled.extend_from_within(scaled.len() - row_bytes..);
+                continue;
+            }
+            for &x in &columns {
+                let i = (row * self.width + x) * 3;
+                scaled.extend_from_slice(&self.pixels[i..i + 3]);
+            }
+            last = Some(row);
+        }
+        Image {
+            width,
+            height,
+            pixels: scaled,
+        }
+    }
+}
+
+/// how strongly each filter is applied, 0 turns it off
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// tint the picture like the cellophane on the cabinet's monitor
+    pub overlay: bool,
+    /// how much darker the gaps between scanlines are, up to 1 for black
+    pub scanlines: f32,
+    /// the part of its brightness a pixel keeps each frame after it goes out
+    pub persistence: f32,
+    /// how much of a blurred copy is added back on top
+    pub bloom: f32,
+    /// how far the tube bulges, about 0.1 looks like a real one
+    pub curvature: f32,
+}
+
+impl Settings {
+    /// everything on at about the strength of a real monitor
+    pub const CRT: Settings = Settings {
+        overlay: true,
+        scanlines: 0.5,
+        persistence: 0.6,
+        bloom: 0.4,
+        curvature: 0.08,
+    };
+}
+
+/// `crt` for the preset, or a comma separated list of `overlay` and
+/// `filter=strength`, e.g. `overlay,scanlines=0.3,persistence=0.7`
+impl FromStr for Settings {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        if list == "crt" {
+            return Ok(Settings::CRT);
+        }
+        let mut settings = Settings::default();
+        for filter in list.split(',').map(str::trim) {
+            if filter == "overlay" {
+                settings.overlay = true;
+                continue;
+            }
+            let (name, value) = filter
+                .split_once('=')
+                .ok_or_else(|| format!("expected filter=strength: {}", filter))?;
+            let value: f32 = value
+                .parse()
+                .map_err(|_| format!("bad strength: {}", value))?;
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("strength out of 0 to 1: {}", value));
+            }
+            match name {
+                "scanlines" => settings.scanlines = value,
+                "persistence" => settings.persistence = value,
+                "bloom" => settings.bloom = value,
+                "curvature" => settings.curvature = value,
+                _ => return Err(format!("unknown filter: {}", name)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// the filters as they run frame after frame, persistence needs the last frame
+#[derive(Debug, Clone)]
+pub struct Crt {
+    pub settings: Settings,
+    previous: Option<Image>,
+    /// the curvature `warp` for the last size and amount
+    warp: Option<(usize, usize, f32, Vec<u32>)>,
+}
+
+impl Crt {
+    pub fn new(settings: Settings) -> Self {
+        Crt {
+            settings,
+            previous: None,
+            warp: None,
+        }
+    }
+
+    /// filter the next frame from `screen::render`, scaled to `width` by `height`
+    pub fn apply(&mut self, pixels: &[u8], width: usize, height: usize) -> Image {
+        let settings = self.settings;
+        let mut image = Image::from_screen(pixels, settings.overlay);
+        if settings.persistence > 0.0 {
+            if let Some(previous) = &self.previous {
+                persist(&mut image, previous, settings.persistence);
+            }
+            self.previous = Some(image.clone());
+        }
+        let mut image = image.scale(width, height);
+        if settings.bloom > 0.0 {
+            // a glow about as wide as a screen pixel and a half
+            let radius = (width / WIDTH * 3 / 2).max(1);
+            bloom(&mut image, radius, settings.bloom);
+        }
+        if settings.scanlines > 0.0 {
+            scanlines(&mut image, HEIGHT, settings.scanlines);
+        }
+        if settings.curvature > 0.0 {
+            let amount = settings.curvature;
+            let (_, _, _, map) = match &self.warp {
+                Some(warp) if (warp.0, warp.1, warp.2) == (width, height, amount) => warp,
+                _ => self
+                    .warp
+                    .insert((width, height, amount, warp(width, height, amount))),
+            };
+            image = remap(&image, map);
+        }
+        image
+    }
+}
+
+/// phosphor decay: each pixel keeps `persistence` of the brightness it had in
+/// `previous` if that is brighter than what is drawn now
+pub fn persist(image: &mut Image, previous: &Image, persistence: f32) {
+    for (pixel, &old) in image.pixels.iter_mut().zip(&previous.pixels) {
+        let faded = (old as f32 * persistence) as u8;
+        *pixel = (*pixel).max(faded);
+    }
+}
+
+/// darken the lower half of each of `lines` scanlines, which only shows once the
+/// picture is at least twice their number high
+pub fn scanlines(image: &mut Image, lines: usize, strength: f32) {
+    let row_bytes = image.width * 3;
+    let factor = ((1.0 - strength) * 256.0) as u32;
+    for y in 0..image.height {
+        if y * lines * 2 / image.height % 2 == 1 {
+            for pixel in &mut image.pixels[y * row_bytes..(y + 1) * row_bytes] {
+                *pixel = ((*pixel as u32 * factor) >> 8) as u8;
+            }
+        }
+    }
+}
+
+/// add `strength` of a box blurred copy `radius` pixels each way, so bright
+/// pixels glow onto their neighbours
+pub fn bloom(image: &mut Image, radius: usize, strength: f32) {
+    let (width, height) = (image.width, image.height);
+    let row_bytes = width * 3;
+    let source = image.pixels.clone();
+    let row = |y: usize| &source[y * row_bytes..(y + 1) * row_bytes];
+    // the average times the strength, in 16 bit fixed point to save dividing
+    let area = ((2 * radius + 1) * (2 * radius + 1)) as f32;
+    let factor = (strength * 65536.0 / area) as u32;
+
+    // sums down each column over the window, zero past the edges, kept a row at
+    // a time
+    let mut columns = vec![0u32; row_bytes];
+    for y in 0..radius.min(height) {
+        columns
+            .iter_mut()
+            .zip(row(y))
+            .for_each(|(sum, &add)| *sum += add as u32);
+    }
+    for (y, pixels) in image.pixels.chunks_exact_mut(row_bytes).enumerate() {
+        if y + radius < height {
+            columns
+                .iter_mut()
+                .zip(row(y + radius))
+                .for_each(|(sum, &add)| *sum += add as u32);
+        }
+        if y > radius {
+            columns
+                .iter_mut()
+                .zip(row(y - radius - 1))
+                .for_each(|(sum, &sub)| *sum -= sub as u32);
+        }
+
+        // and those summed across the window
+        let column = |x: usize| &columns[x * 3..x * 3 + 3];
+        let mut sum = [0u32; 3];
+        for x in 0..radius.min(width) {
+            sum.iter_mut()
+                .zip(column(x))
+                .for_each(|(sum, &add)| *sum += add);
+        }
+        for (x, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+            if x + radius < width {
+                sum.iter_mut()
+                    .zip(column(x + radius))
+                    .for_each(|(sum, &add)| *sum += add);
+            }
+            if x > radius {
+                sum.iter_mut()
+                    .zip(column(x - radius - 1))
+                    .for_each(|(sum, &sub)| *sum -= sub);
+            }
+            for (channel, &sum) in pixel.iter_mut().zip(&sum) {
+                *channel = (*channel as u32 + ((sum * factor) >> 16)).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// bulge the picture like the face of a tube, blacking out what falls off the
+/// rounded edges
+pub fn curve(image: &Image, amount: f32) -> Image {
+    remap(image, &warp(image.width, image.height, amount))
+}
+
+/// marks a pixel off the edges in a `warp`
+const OFF: u32 = u32::MAX;
+
+/// the pixel each pixel of a `width` by `height` picture bulged by `amount` is
+/// taken from, `OFF` for those off the edges
+fn warp(width: usize, height: usize, amount: f32) -> Vec<u32> {
+    let mut map = Vec::with_capacity(width * height);
+    for y in 0..height {
+        // -1 to 1 across the picture
+        let v = (2 * y + 1) as f32 / height as f32 - 1.0;
+        for x in 0..width {
+            let u = (2 * x + 1) as f32 / width as f32 - 1.0;
+            let source_u = u * (1.0 + amount * v * v);
+            let source_v = v * (1.0 + amount * u * u);
+            map.push(if source_u.abs() >= 1.0 || source_v.abs() >= 1.0 {
+                OFF
+            } else {
+                let source_x = ((source_u + 1.0) * width as f32 / 2.0) as usize;
+                let source_y = ((source_v + 1.0) * height as f32 / 2.0) as usize;
+                (source_y * width + source_x) as u32
+            });
+        }
+    }
+    map
+}
+
+fn remap(image: &Image, map: &[u32]) -> Image {
+    let mut remapped = Image::new(image.width, image.height);
+    for (to, &from) in remapped.pixels.chunks_exact_mut(3).zip(map) {
+        if from != OFF {
+            let from = from as usize * 3;
+            to.copy_from_slice(&image.pixels[from..from + 3]);
+        }
+    }
+    remapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bloom, curve, persist, scanlines, Crt, Image, Settings};
+    use crate::screen::{HEIGHT, WIDTH};
+
+    fn white(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0xFF; width * height * 3],
+        }
+    }
+
+    #[test]
+    fn test_scanlines_and_curvature() {
+        let mut image = white(4, 8);
+        scanlines(&mut image, 4, 0.5);
+        let rows: Vec<u8> = image.pixels.chunks(12).map(|row| row[0]).collect();
+        assert_eq!(rows, [0xFF, 0x7F, 0xFF, 0x7F, 0xFF, 0x7F, 0xFF, 0x7F]);
+        // too few rows to show them
+        let mut image = white(4, 4);
+        scanlines(&mut image, 4, 0.5);
+        assert_eq!(image, white(4, 4));
+
+        let image = curve(&white(64, 64), 0.2);
+        let at = |x: usize, y: usize| image.pixels[(y * 64 + x) * 3];
+        assert_eq!(at(32, 32), 0xFF);
+        assert_eq!(at(32, 0), 0xFF);
+        assert_eq!(at(0, 0), 0);
+        assert_eq!(at(63, 63), 0);
+        assert_eq!(curve(&white(64, 64), 0.0), white(64, 64));
+    }
+
+    #[test]
+    fn test_persistence_and_bloom() {
+        let lit = white(1, 1);
+        let mut image = Image::new(1, 1);
+        persist(&mut image, &lit, 0.5);
+        assert_eq!(image.pixels, [0x7F; 3]);
+        let previous = image;
+        let mut image = Image::new(1, 1);
+        persist(&mut image, &previous, 0.5);
+        assert_eq!(image.pixels, [0x3F; 3]);
+        // a pixel lit again is at full brightness
+        let mut image = white(1, 1);
+        persist(&mut image, &previous, 0.5);
+        assert_eq!(image, white(1, 1));
+
+        let mut image = Image::new(5, 5);
+        image.pixels[12 * 3..12 * 3 + 3].fill(0xFF);
+        bloom(&mut image, 1, 1.0);
+        let at = |x: usize, y: usize| image.pixels[(y * 5 + x) * 3];
+        assert_eq!(at(2, 2), 0xFF);
+        assert_eq!(at(1, 1), 0xFF / 9);
+        assert_eq!(at(3, 2), 0xFF / 9);
+        assert_eq!(at(0, 0), 0);
+        let mut black = Image::new(5, 5);
+        bloom(&mut black, 2, 1.0);
+        assert_eq!(black, Image::new(5, 5));
+    }
+
+    #[test]
+    fn test_settings_and_frames() {
+        assert_eq!("crt".parse(), Ok(Settings::CRT));
+        let settings: Settings = "overlay,scanlines=0.25".parse().unwrap();
+        assert!(settings.overlay);
+        assert_eq!(settings.scanlines, 0.25);
+        assert_eq!(settings.bloom, 0.0);
+        assert!("bloom=2".parse::<Settings>().is_err());
+        assert!("glow=0.5".parse::<Settings>().is_err());
+
+        // a pixel of the saucer goes out and fades
+        let mut pixels = vec![0; WIDTH * HEIGHT];
+        pixels[40 * WIDTH + 100] = 0xFF;
+        let mut crt = Crt::new("overlay,persistence=0.5".parse().unwrap());
+        let image = crt.apply(&pixels, WIDTH, HEIGHT);
+        let i = (40 * WIDTH + 100) * 3;
+        assert_eq!(image.pixels[i..i + 3], [0xFF, 0x30, 0x30]);
+        let image = crt.apply(&vec![0; WIDTH * HEIGHT], WIDTH * 2, HEIGHT * 2);
+        let i = (81 * WIDTH * 2 + 201) * 3;
+        assert_eq!(image.pixels[i..i + 3], [0x7F, 0x18, 0x18]);
+    }
+}
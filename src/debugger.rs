@@ -0,0 +1,877 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    breakpoints::{parse_hex, Condition, Event, Kind},
+    callstack::FrameKind,
+    instructions::Instruction,
+    machine::Machine,
+    symbols::Symbols,
+};
+
+/// `continue`, `next` and `until` give up after this many instructions
+const RUN_LIMIT: u64 = 100_000_000;
+/// instructions kept for stepping backwards, unless the machine already keeps a history
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+commands, addresses and values are hex, counts are decimal:
+  s, step [n]            execute n instructions (default 1)
+  n, next                step over CALL and RST
+  c, continue [n]        run until halted or for n instructions
+  u, until <addr>        run until PC reaches addr
+  rs, rstep [n]          step back n instructions (default 1)
+  rc, rcontinue          run backwards until a breakpoint or the start of the history
+  who <addr>             show the last instruction in the history that wrote addr
+  history [n]            keep the last n instructions for stepping back, 0 to disable
+  r, regs                show registers and flags
+  m, mem <addr> [len]    dump memory (default 0x40 bytes)
+  d, dis [addr] [n]      disassemble n instructions (default PC, 10)
+  set <reg> <value>      set a, b, c, d, e, h, l, bc, de, hl, sp, pc or psw
+  stack [base]           show the words between SP and base (default 2400 for Invaders)
+  bt, backtrace          show the calls, restarts and interrupts not yet returned from
+  b, break <addr>        stop when PC reaches addr
+  watch <addr>[-<end>]   stop on writes to the range
+  rwatch <addr>[-<end>]  stop on reads from the range
+  awatch <addr>[-<end>]  stop on reads and writes
+  io <port>[-<end>] [in|out]
+                         stop on IN and/or OUT with the port
+                         any breakpoint can end with [if <reg> <op> <value>] [count <n>],
+                         e.g. break 1A5C if a == 10 count 3
+  bl, breaks             list breakpoints
+  del <id>|all           delete breakpoints
+  sym [file]             load a symbol file, or show how many symbols are loaded
+  h, help                show this help
+  q, quit                leave the debugger
+addresses can also be symbol names, an empty line repeats the last command";
+
+/// an interactive command line debugger around any `Machine`
+pub struct Debugger<M: Machine> {
+    pub machine: M,
+    /// names used in disassembly and accepted in place of addresses
+    pub symbols: Symbols,
+    stack_base: Option<u16>,
+    last_command: String,
+}
+
+impl<M: Machine> Debugger<M> {
+    pub fn new(mut machine: M) -> Self {
+        let history = &mut machine.cpu_mut().history;
+        if !history.is_enabled() {
+            history.set_capacity(HISTORY);
+        }
+        Debugger {
+            stack_base: machine.stack_base(),
+            machine,
+            symbols: Symbols::new(),
+            last_command: String::new(),
+        }
+    }
+
+    /// read commands until `quit` or the end of input
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        self.show_next(&mut out)?;
+        write!(out, "(8080) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, &mut out)? {
+                break;
+            }
+            write!(out, "(8080) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// execute a single command, returns false when the debugger should exit
+    pub fn command<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = words.collect();
+        match self.dispatch(name, &args, out) {
+            Ok(keep_going) => Ok(keep_going),
+            Err(Error::Io(err)) => Err(err),
+            Err(Error::Usage(message)) => {
+                writeln!(out, "{}", message)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn dispatch<W: Write>(
+        &mut self,
+        name: &str,
+        args: &[&str],
+        out: &mut W,
+    ) -> Result<bool, Error> {
+        match name {
+            "s" | "step" => {
+                let count = arg_count(args.first(), 1)?;
+                self.run_until(out, count, |_| false)?;
+                self.show_next(out)?;
+            }
+            "n" | "next" => {
+                self.next(out)?;
+                self.show_next(out)?;
+            }
+            "c" | "continue" => {
+                let count = arg_count(args.first(), RUN_LIMIT)?;
+                self.run_until(out, count, |_| false)?;
+                self.show_next(out)?;
+            }
+            "u" | "until" => {
+                let address = self.arg_address(args.first())?;
+                self.run_until(out, RUN_LIMIT, |machine| machine.cpu().pc == address)?;
+                self.show_next(out)?;
+            }
+            "rs" | "rstep" => {
+                let count = arg_count(args.first(), 1)?;
+                self.run_back(out, count, false)?;
+                self.show_next(out)?;
+            }
+            "rc" | "rcontinue" => {
+                self.run_back(out, u64::MAX, true)?;
+                self.show_next(out)?;
+            }
+            "who" => {
+                let address = self.arg_address(args.first())?;
+                self.who(out, address)?;
+            }
+            "history" => {
+                let history = &mut self.machine.cpu_mut().history;
+                if let Some(capacity) = args.first() {
+                    let capacity = capacity
+                        .parse()
+                        .map_err(|_| Error::Usage(format!("not a count: {}", capacity)))?;
+                    history.set_capacity(capacity);
+                }
+                writeln!(
+                    out,
+                    "{} of {} instructions recorded",
+                    history.len(),
+                    history.capacity()
+                )?;
+            }
+            "r" | "regs" => writeln!(out, "{}", self.machine.cpu().state())?,
+            "m" | "mem" => {
+                let address = self.arg_address(args.first())?;
+                let len = match args.get(1) {
+                    Some(len) => parse_hex(len).map_err(Error::Usage)?,
+                    None => 0x40,
+                };
+                self.dump(out, address, len)?;
+            }
+            "d" | "dis" => {
+                let address = match args.first() {
+                    Some(address) => self.parse_address(address)?,
+                    None => self.machine.cpu().pc,
+                };
+                let count = arg_count(args.get(1), 10)?;
+                self.disassemble(out, address, count)?;
+            }
+            "set" => {
+                let (register, value) = match args {
+                    [register, value] => (*register, parse_hex(value).map_err(Error::Usage)?),
+                    _ => return Err(Error::Usage("usage: set <reg> <value>".into())),
+                };
+                self.set_register(register, value)?;
+                writeln!(out, "{}", self.machine.cpu().state())?;
+            }
+            "stack" => {
+                if let Some(base) = args.first() {
+                    self.stack_base = Some(parse_hex(base).map_err(Error::Usage)?);
+                }
+                self.stack(out)?;
+            }
+            "bt" | "backtrace" => self.backtrace(out)?,
+            "b" | "break" | "watch" | "rwatch" | "awatch" | "io" => {
+                let id = self.add_breakpoint(name, args)?;
+                writeln!(out, "breakpoint {}", id)?;
+            }
+            "bl" | "breaks" => {
+                let breakpoints = &self.machine.cpu().breakpoints;
+                if breakpoints.is_empty() {
+                    writeln!(out, "no breakpoints")?;
+                }
+                for breakpoint in breakpoints.iter() {
+                    writeln!(out, "{}", breakpoint)?;
+                }
+            }
+            "del" => match args.first() {
+                Some(&"all") => self.machine.cpu_mut().breakpoints.clear(),
+                Some(id) => {
+                    let id = id
+                        .parse()
+                        .map_err(|_| Error::Usage(format!("not a breakpoint id: {}", id)))?;
+                    if !self.machine.cpu_mut().breakpoints.remove(id) {
+                        return Err(Error::Usage(format!("no breakpoint {}", id)));
+                    }
+                }
+                None => return Err(Error::Usage("usage: del <id>|all".into())),
+            },
+            "sym" => {
+                if let Some(path) = args.first() {
+                    self.symbols = Symbols::load(path)
+                        .map_err(|err| Error::Usage(format!("{}: {}", path, err)))?;
+                }
+                writeln!(out, "{} symbols", self.symbols.len())?;
+            }
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => return Err(Error::Usage(format!("unknown command: {}, try help", name))),
+        }
+        Ok(true)
+    }
+
+    /// step over calls by running until the return address is reached with the stack unwound
+    fn next<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        use Instruction::*;
+        let cpu = self.machine.cpu();
+        let (instruction, size) = cpu.fetch();
+        let is_call = matches!(
+            instruction,
+            CALL_ADR(_)
+                | CNZ_ADR(_)
+                | CZ_ADR(_)
+                | CNC_ADR(_)
+                | CC_ADR(_)
+                | CPO_ADR(_)
+                | CPE_ADR(_)
+                | CP_ADR(_)
+                | CM_ADR(_)
+                | RST_0
+                | RST_1
+                | RST_2
+                | RST_3
+                | RST_4
+                | RST_5
+                | RST_6
+                | RST_7
+        );
+        if !is_call {
+            self.run_until(out, 1, |_| false)?;
+            return Ok(());
+        }
+        let return_address = cpu.pc.wrapping_add(size as u16);
+        let sp = cpu.sp;
+        if self.run_until(out, 1, |_| false)? {
+            return Ok(());
+        }
+        self.run_until(out, RUN_LIMIT, |machine| {
+            let cpu = machine.cpu();
+            cpu.pc == return_address && cpu.sp >= sp
+        })?;
+        Ok(())
+    }
+
+    /// step until `stop` returns true, a breakpoint is hit, the cpu halts or `limit`
+    /// instructions have run, returns true if it stopped early for any reason but `stop`
+    fn run_until<W: Write, F: Fn(&M) -> bool>(
+        &mut self,
+        out: &mut W,
+        limit: u64,
+        stop: F,
+    ) -> io::Result<bool> {
+        // a hit left over from outside the debugger should not stop the first step
+        self.machine.cpu_mut().take_hit();
+        for _ in 0..limit {
+            if stop(&self.machine) {
+                return Ok(false);
+            }
+            let cpu = self.machine.cpu();
+            if cpu.is_halted() && !cpu.interrupts_enabled() {
+                writeln!(out, "halted")?;
+                return Ok(true);
+            }
+            self.machine.step();
+            if let Some(hit) = self.machine.cpu_mut().take_hit() {
+                writeln!(out, "{}", hit)?;
+                return Ok(true);
+            }
+        }
+        if limit == RUN_LIMIT {
+            writeln!(out, "stopped after {} instructions", limit)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// step backwards `limit` times, or until an execution breakpoint is reached or
+    /// a write watchpoint is undone when `stop_at_breakpoints`, conditions are checked
+    /// against the state before the instruction
+    fn run_back<W: Write>(
+        &mut self,
+        out: &mut W,
+        limit: u64,
+        stop_at_breakpoints: bool,
+    ) -> io::Result<()> {
+        for _ in 0..limit {
+            let undone = self.machine.step_back();
+            if undone.is_empty() {
+                writeln!(out, "start of history")?;
+                return Ok(());
+            }
+            if !stop_at_breakpoints {
+                continue;
+            }
+            let cpu = self.machine.cpu();
+            let written = undone.iter().flat_map(|entry| &entry.writes);
+            for &(address, _, value) in written {
+                let ids = cpu
+                    .breakpoints
+                    .matching(cpu, Event::Write, address, Some(value));
+                if let Some(&index) = ids.first() {
+                    let id = cpu.breakpoints.iter().nth(index).map_or(0, |b| b.id);
+                    writeln!(
+                        out,
+                        "breakpoint {}: write to {:04X}, value {:02X}",
+                        id, address, value
+                    )?;
+                    return Ok(());
+                }
+            }
+            let ids = cpu.breakpoints.matching(cpu, Event::Execute, cpu.pc, None);
+            if let Some(&index) = ids.first() {
+                let id = cpu.breakpoints.iter().nth(index).map_or(0, |b| b.id);
+                writeln!(out, "breakpoint {}: reached {:04X}", id, cpu.pc)?;
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn who<W: Write>(&self, out: &mut W, address: u16) -> io::Result<()> {
+        let cpu = self.machine.cpu();
+        match cpu.history.last_write(address) {
+            Some((entry, old)) => {
+                let pc = entry.state.pc;
+                let (instruction, _) = cpu.fetch_at(pc);
+                let what = if entry.interrupt {
+                    "interrupt".to_string()
+                } else {
+                    self.symbols.instruction(&instruction)
+                };
+                writeln!(
+                    out,
+                    "{:04X} last written at {:04X}{} ({}) at cycle {}, old value {:02X}",
+                    address,
+                    pc,
+                    self.symbols
+                        .locate(pc)
+                        .map(|_| format!(" {}", self.symbols.describe(pc)))
+                        .unwrap_or_default(),
+                    what,
+                    entry.state.cycles,
+                    old
+                )
+            }
+            None => writeln!(
+                out,
+                "{:04X} not written in the last {} instructions",
+                address,
+                cpu.history.len()
+            ),
+        }
+    }
+
+    /// `break <addr>`, `watch <range>` and friends, with an optional condition and count
+    fn add_breakpoint(&mut self, name: &str, args: &[&str]) -> Result<usize, Error> {
+        let (range, mut rest) = match args.split_first() {
+            Some((range, rest)) => (*range, rest),
+            None => return Err(Error::Usage(format!("usage: {} <addr>", name))),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.parse_address(start)?, self.parse_address(end)?),
+            None => {
+                let address = self.parse_address(range)?;
+                (address, address)
+            }
+        };
+        let kind = match name {
+            "watch" => Kind::Write,
+            "rwatch" => Kind::Read,
+            "awatch" => Kind::Access,
+            "io" => match rest.first() {
+                Some(&"in") => {
+                    rest = &rest[1..];
+                    Kind::In
+                }
+                Some(&"out") => {
+                    rest = &rest[1..];
+                    Kind::Out
+                }
+                _ => Kind::Io,
+            },
+            _ => Kind::Execute,
+        };
+        if matches!(kind, Kind::In | Kind::Out | Kind::Io) && end > 0xFF {
+            return Err(Error::Usage(format!("not a port: {:X}", end)));
+        }
+
+        let mut condition = None;
+        let mut count = 1;
+        let mut words = rest;
+        while let Some((&word, tail)) = words.split_first() {
+            match word {
+                "if" => {
+                    let len = tail
+                        .iter()
+                        .position(|&w| w == "count")
+                        .unwrap_or(tail.len());
+                    condition =
+                        Some(Condition::parse(&tail[..len].join(" ")).map_err(Error::Usage)?);
+                    words = &tail[len..];
+                }
+                "count" => {
+                    count = arg_count(tail.first(), 1)?.max(1);
+                    words = tail.get(1..).unwrap_or(&[]);
+                }
+                _ => return Err(Error::Usage(format!("unexpected: {}", word))),
+            }
+        }
+        Ok(self
+            .machine
+            .cpu_mut()
+            .breakpoints
+            .add(kind, start, end, condition, count))
+    }
+
+    fn show_next<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.disassemble(out, self.machine.cpu().pc, 1)
+    }
+
+    fn disassemble<W: Write>(&self, out: &mut W, mut address: u16, count: u64) -> io::Result<()> {
+        let cpu = self.machine.cpu();
+        for _ in 0..count {
+            if let Some(name) = self.symbols.name(address) {
+                writeln!(out, "{}:", name)?;
+            }
+            let (instruction, size) = cpu.fetch_at(address);
+            let bytes: Vec<String> = (0..size as u16)
+                .map(|i| format!("{:02X}", cpu.read_byte(address.wrapping_add(i))))
+                .collect();
+            let marker = if address == cpu.pc { "=>" } else { "  " };
+            writeln!(
+                out,
+                "{} {:04X}  {:<8}  {}",
+                marker,
+                address,
+                bytes.join(" "),
+                self.symbols.instruction(&instruction)
+            )?;
+            address = address.wrapping_add(size as u16);
+        }
+        Ok(())
+    }
+
+    fn dump<W: Write>(&self, out: &mut W, address: u16, len: u16) -> io::Result<()> {
+        let cpu = self.machine.cpu();
+        for row in (0..len).step_by(16) {
+            let start = address.wrapping_add(row);
+            let bytes: Vec<u8> = (0..16.min(len - row))
+                .map(|i| cpu.read_byte(start.wrapping_add(i)))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(out, "{:04X}  {:<47}  {}", start, hex.join(" "), text)?;
+        }
+        Ok(())
+    }
+
+    fn stack<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let cpu = self.machine.cpu();
+        if cpu.sp == 0 {
+            return writeln!(out, "Stack pointer not set");
+        }
+        let base = match self.stack_base {
+            Some(base) => base,
+            None => return writeln!(out, "No stack base, give one with stack <base>"),
+        };
+        let bytes = match cpu.stack(base) {
+            Some(bytes) => bytes,
+            None => return writeln!(out, "SP = {:04X} is above base {:04X}", cpu.sp, base),
+        };
+        writeln!(out, "SP = {:04X}, base = {:04X}", cpu.sp, base)?;
+        for (i, word) in bytes.chunks(2).enumerate() {
+            let address = cpu.sp.wrapping_add(2 * i as u16);
+            match word {
+                [lo, hi] => writeln!(
+                    out,
+                    "  {:04X}: {:04X}",
+                    address,
+                    u16::from_le_bytes([*lo, *hi])
+                )?,
+                [lo] => writeln!(out, "  {:04X}: {:02X}", address, lo)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// a symbol name or a hex address
+    fn parse_address(&self, text: &str) -> Result<u16, Error> {
+        match self.symbols.address(text) {
+            Some(address) => Ok(address),
+            None => parse_hex(text).map_err(Error::Usage),
+        }
+    }
+
+    fn arg_address(&self, arg: Option<&&str>) -> Result<u16, Error> {
+        match arg {
+            Some(arg) => self.parse_address(arg),
+            None => Err(Error::Usage("missing address".into())),
+        }
+    }
+
+    fn backtrace<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let cpu = self.machine.cpu();
+        writeln!(out, "#0  {:04X}  {}", cpu.pc, self.symbols.describe(cpu.pc))?;
+        for (i, frame) in cpu.call_stack.active(cpu.sp).enumerate() {
+            let target = self.symbols.describe(frame.target);
+            let how = match frame.kind {
+                FrameKind::Call => format!("CALL {}", target),
+                FrameKind::Restart => format!("RST {}", frame.target / 8),
+                FrameKind::Interrupt => format!("interrupt RST {}", frame.target / 8),
+            };
+            write!(
+                out,
+                "#{:<2} {:04X}  {:<24}  {}",
+                i + 1,
+                frame.caller,
+                self.symbols.describe(frame.caller),
+                how
+            )?;
+            // the program changed the return address on the real stack
+            let pushed = u16::from_le_bytes([
+                cpu.read_byte(frame.sp),
+                cpu.read_byte(frame.sp.wrapping_add(1)),
+            ]);
+            if pushed != frame.return_address {
+                write!(
+                    out,
+                    ", return address at {:04X} changed to {:04X}",
+                    frame.sp, pushed
+                )?;
+            }
+            writeln!(out)?;
+        }
+        let mismatches: Vec<_> = cpu.call_stack.mismatches().collect();
+        if !mismatches.is_empty() {
+            writeln!(out, "mismatched returns, most recent last:")?;
+            for mismatch in mismatches {
+                writeln!(out, "  {}", mismatch)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_register(&mut self, register: &str, value: u16) -> Result<(), Error> {
+        let cpu = self.machine.cpu_mut();
+        let byte = || {
+            u8::try_from(value)
+                .map_err(|_| Error::Usage(format!("{:#X} does not fit in {}", value, register)))
+        };
+        match register.to_ascii_lowercase().as_str() {
+            "a" => cpu.a = byte()?,
+            "b" => cpu.b = byte()?,
+            "c" => cpu.c = byte()?,
+            "d" => cpu.d = byte()?,
+            "e" => cpu.e = byte()?,
+            "h" => cpu.h = byte()?,
+            "l" => cpu.l = byte()?,
+            "psw" | "f" => cpu.set_psw(byte()?),
+            "bc" => cpu.set_bc(value),
+            "de" => cpu.set_de(value),
+            "hl" => cpu.set_hl(value),
+            "sp" => cpu.sp = value,
+            "pc" => cpu.pc = value,
+            _ => return Err(Error::Usage(format!("unknown register: {}", register))),
+        }
+        Ok(())
+    }
+}
+
+enum Error {
+    Io(io::Error),
+    /// a bad command, reported to the user without stopping the debugger
+    Usage(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+fn arg_count(arg: Option<&&str>, default: u64) -> Result<u64, Error> {
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| Error::Usage(format!("not a count: {}", arg))),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::{cpu::Cpu, history::Entry, machine::Machine, symbols::Symbols};
+
+    fn start(program: &[u8]) -> Debugger<Cpu> {
+        let mut cpu = Cpu::new();
+        cpu.load(program, 0);
+        Debugger::new(cpu)
+    }
+
+    fn command(debugger: &mut Debugger<Cpu>, line: &str) -> String {
+        let mut out = Vec::new();
+        assert!(debugger.command(line, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    // LXI SP,0x2400; CALL 0x0008; HLT; NOP; MVI A,0x42; RET
+    const PROGRAM: [u8; 12] = [
+        0x31, 0x00, 0x24, 0xCD, 0x08, 0x00, 0x76, 0x00, 0x3E, 0x42, 0xC9, 0x00,
+    ];
+
+    #[test]
+    fn test_step_and_disassemble() {
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(
+            command(&mut debugger, "step"),
+            "=> 0003  CD 08 00  CALL 0x0008\n"
+        );
+        // an empty line repeats the step
+        assert_eq!(
+            command(&mut debugger, ""),
+            "=> 0008  3E 42     MVI A,0x42\n"
+        );
+        let listing = command(&mut debugger, "dis 0 3");
+        assert_eq!(
+            listing,
+            "   0000  31 00 24  LXI SP,0x2400\n   0003  CD 08 00  CALL 0x0008\n   0006  76        HLT\n"
+        );
+    }
+
+    #[test]
+    fn test_next_steps_over_call() {
+        let mut debugger = start(&PROGRAM);
+        command(&mut debugger, "s");
+        assert_eq!(command(&mut debugger, "next"), "=> 0006  76        HLT\n");
+        assert_eq!(debugger.machine.a, 0x42);
+        assert_eq!(debugger.machine.sp, 0x2400);
+    }
+
+    #[test]
+    fn test_continue_and_until() {
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(
+            command(&mut debugger, "c"),
+            "halted\n=> 0007  00        NOP\n"
+        );
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(
+            command(&mut debugger, "until 8"),
+            "=> 0008  3E 42     MVI A,0x42\n"
+        );
+    }
+
+    #[test]
+    fn test_set_and_memory() {
+        let mut debugger = start(&PROGRAM);
+        command(&mut debugger, "set hl 2400");
+        command(&mut debugger, "set a 41");
+        assert_eq!(debugger.machine.hl(), 0x2400);
+        assert_eq!(
+            command(&mut debugger, "set a 141"),
+            "0x141 does not fit in a\n"
+        );
+        assert_eq!(command(&mut debugger, "set x 1"), "unknown register: x\n");
+        assert_eq!(
+            command(&mut debugger, "mem 0 10"),
+            "0000  31 00 24 CD 08 00 76 00 3E 42 C9 00 00 00 00 00  1.$...v.>B......\n"
+        );
+    }
+
+    #[test]
+    fn test_stack_view() {
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(command(&mut debugger, "stack"), "Stack pointer not set\n");
+        command(&mut debugger, "s 2");
+        // a bare cpu has no stack base of its own
+        assert_eq!(
+            command(&mut debugger, "stack"),
+            "No stack base, give one with stack <base>\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "stack 2400"),
+            "SP = 23FE, base = 2400\n  23FE: 0006\n"
+        );
+        // the base is kept
+        assert_eq!(
+            command(&mut debugger, "stack"),
+            "SP = 23FE, base = 2400\n  23FE: 0006\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "stack 2000"),
+            "SP = 23FE is above base 2000\n"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(command(&mut debugger, "break 8"), "breakpoint 1\n");
+        assert_eq!(
+            command(&mut debugger, "c"),
+            "breakpoint 1: reached 0008\n=> 0008  3E 42     MVI A,0x42\n"
+        );
+        // the return address pushed by the CALL
+        assert_eq!(
+            command(&mut debugger, "rwatch 23fe-23ff if value == 6"),
+            "breakpoint 2\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "c"),
+            "breakpoint 2: read from 23FE, value 06\n=> 0006  76        HLT\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "bl"),
+            "1   execute 0008, hit 1 times\n2   read    23FE-23FF if value == 0x6, hit 1 times\n"
+        );
+        command(&mut debugger, "del all");
+        assert_eq!(command(&mut debugger, "bl"), "no breakpoints\n");
+        assert_eq!(
+            command(&mut debugger, "break 8 if q == 1"),
+            "unknown operand: q\n"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut debugger = start(&PROGRAM);
+        debugger.symbols = Symbols::parse("0008 SetA");
+        assert_eq!(
+            command(&mut debugger, "dis 3 1"),
+            "   0003  CD 08 00  CALL SetA\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "until SetA"),
+            "SetA:\n=> 0008  3E 42     MVI A,0x42\n"
+        );
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut debugger = start(&PROGRAM);
+        debugger.symbols = Symbols::parse("0000 Start\n0008 SetA");
+        command(&mut debugger, "s 3");
+        assert_eq!(
+            command(&mut debugger, "bt"),
+            "#0  000A  SetA+0x2\n#1  0003  Start+0x3                 CALL SetA\n"
+        );
+        // the program overwrites its return address
+        debugger.machine.write_byte(0x23FE, 0x07);
+        command(&mut debugger, "s");
+        assert_eq!(
+            command(&mut debugger, "bt"),
+            "#0  0007  Start+0x7\nmismatched returns, most recent last:\n  RET at 000A returned to 0007, expected 0006\n"
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut debugger = start(&PROGRAM);
+        command(&mut debugger, "c");
+        assert_eq!(command(&mut debugger, "rs 2"), "=> 000A  C9        RET\n");
+        assert_eq!(debugger.machine.a, 0x42);
+        assert_eq!(
+            command(&mut debugger, "who 23fe"),
+            "23FE last written at 0003 (CALL 0x0008) at cycle 10, old value 00\n"
+        );
+        command(&mut debugger, "break 3");
+        assert_eq!(
+            command(&mut debugger, "rc"),
+            "breakpoint 1: reached 0003\n=> 0003  CD 08 00  CALL 0x0008\n"
+        );
+        assert_eq!(debugger.machine.sp, 0x2400);
+        assert_eq!(
+            command(&mut debugger, "rc"),
+            "start of history\n=> 0000  31 00 24  LXI SP,0x2400\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "history"),
+            "0 of 100000 instructions recorded\n"
+        );
+    }
+
+    /// steps three instructions at a time, so stepping back undoes three entries
+    struct Grouped(Cpu);
+
+    impl Machine for Grouped {
+        fn cpu(&self) -> &Cpu {
+            &self.0
+        }
+
+        fn cpu_mut(&mut self) -> &mut Cpu {
+            &mut self.0
+        }
+
+        fn step(&mut self) {
+            for _ in 0..3 {
+                self.0.step();
+            }
+        }
+
+        fn step_back(&mut self) -> Vec<Entry> {
+            (0..3).map_while(|_| self.0.step_back()).collect()
+        }
+    }
+
+    #[test]
+    fn test_reverse_watch_in_any_undone_entry() {
+        // STA 0x2000; NOP; NOP; HLT
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x32, 0x00, 0x20, 0x00, 0x00, 0x76], 0);
+        let mut debugger = Debugger::new(Grouped(cpu));
+        let mut out = Vec::new();
+        debugger.command("s", &mut out).unwrap();
+        debugger.command("watch 2000", &mut out).unwrap();
+        out.clear();
+        // the write is the oldest of the three entries undone
+        debugger.command("rc", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "breakpoint 1: write to 2000, value 00\n=> 0000  32 00 20  STA 0x2000\n"
+        );
+    }
+
+    #[test]
+    fn test_quit_and_unknown() {
+        let mut debugger = start(&PROGRAM);
+        assert_eq!(
+            command(&mut debugger, "frobnicate"),
+            "unknown command: frobnicate, try help\n"
+        );
+        let mut out = Vec::new();
+        assert!(!debugger.command("q", &mut out).unwrap());
+    }
+}
@@ -0,0 +1,590 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    breakpoints::{Event, Hit, Kind},
+    machine::Machine,
+};
+
+/// how many instructions `continue` runs between checks for a ^C from the client
+const POLL_INTERVAL: u64 = 100_000;
+
+/// the registers as sent by `g`, 16 bits each in little endian, with the flags
+/// in the low byte of AF, as the z80 port of gdb numbers them
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.i8080.cpu">
+    <reg name="af" bitsize="16" type="int" regnum="0"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="data_ptr"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const REGISTERS: usize = 6;
+
+/// the most packet data the stub takes or sends, told to the client in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+/// the most bytes `m` and `M` move at once, two hex digits each fit in a packet
+const MAX_TRANSFER: usize = PACKET_SIZE / 2;
+
+/// a gdb remote serial protocol server for a `Machine`, serving one client at a time
+///
+/// supports reading and writing registers and memory, single step, continue with ^C,
+/// software and hardware breakpoints (`Z0`, `Z1`) and watchpoints (`Z2` to `Z4`),
+/// which are kept in the cpu's `Breakpoints` alongside any set elsewhere
+pub struct GdbStub<M: Machine> {
+    pub machine: M,
+    /// (`Z` type, address, breakpoint id) of the breakpoints set by the client
+    inserted: Vec<(u8, u16, usize)>,
+    no_ack: bool,
+}
+
+/// what to do after handling a packet
+enum Action {
+    Reply(String),
+    /// run and reply with the stop reason
+    Step,
+    Continue,
+    /// the client detached or killed the target
+    Close(Option<String>),
+}
+
+impl<M: Machine> GdbStub<M> {
+    pub fn new(machine: M) -> Self {
+        GdbStub {
+            machine,
+            inserted: Vec::new(),
+            no_ack: false,
+        }
+    }
+
+    /// wait for one client on `address` and serve it until it detaches
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    /// serve a connected client until it detaches, kills the target or disconnects
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        self.no_ack = false;
+        while let Some(packet) = self.receive(&mut stream)? {
+            let reply = match self.handle(&packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => self.step(),
+                Action::Continue => self.resume(&mut stream)?,
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        self.send(&mut stream, &reply)?;
+                    }
+                    break;
+                }
+            };
+            self.send(&mut stream, &reply)?;
+        }
+        self.remove_all();
+        Ok(())
+    }
+
+    /// read the next packet, acknowledging it, returns `None` when the client disconnects
+    fn receive(&mut self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and stray ^C until the start of a packet
+            match read_byte(stream)? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            loop {
+                match read_byte(stream)? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok())
+                == Some(checksum_of(&data));
+            if !self.no_ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(unescape(&data)));
+            }
+        }
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, checksum_of(reply.as_bytes()));
+        loop {
+            stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match read_byte(stream)? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> Action {
+        let reply = |reply: &str| Action::Reply(reply.to_string());
+        // `unescape` is lossy, so the first character need not be a single byte
+        let (command, args) = match packet.as_bytes().first() {
+            Some(byte) if byte.is_ascii() => (&packet[..1], &packet[1..]),
+            _ => return self.query(packet),
+        };
+        match command {
+            "?" => reply("S05"),
+            "g" => Action::Reply(self.registers()),
+            "G" => match decode_hex(args) {
+                Some(bytes) if bytes.len() >= REGISTERS * 2 => {
+                    for (number, value) in bytes.chunks(2).take(REGISTERS).enumerate() {
+                        self.set_register(number, u16::from_le_bytes([value[0], value[1]]));
+                    }
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(number) if number < REGISTERS => {
+                    Action::Reply(encode_hex(&self.register(number).to_le_bytes()))
+                }
+                _ => reply("E01"),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(number, value)| {
+                    let number = usize::from_str_radix(number, 16).ok()?;
+                    let value = decode_hex(value)?;
+                    (number < REGISTERS && value.len() == 2).then_some((number, value))
+                });
+                match parsed {
+                    Some((number, value)) => {
+                        self.set_register(number, u16::from_le_bytes([value[0], value[1]]));
+                        reply("OK")
+                    }
+                    None => reply("E01"),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((address, len)) if len <= MAX_TRANSFER => {
+                    let cpu = self.machine.cpu();
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| cpu.read_byte(address.wrapping_add(i as u16)))
+                        .collect();
+                    Action::Reply(encode_hex(&bytes))
+                }
+                _ => reply("E01"),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+                match parsed {
+                    Some(((address, len), data)) if len <= MAX_TRANSFER && data.len() == len => {
+                        let cpu = self.machine.cpu_mut();
+                        for (i, byte) in data.into_iter().enumerate() {
+                            cpu.write_byte(address.wrapping_add(i as u16), byte);
+                        }
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "s" | "c" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    self.machine.cpu_mut().pc = address;
+                }
+                if command == "s" {
+                    Action::Step
+                } else {
+                    Action::Continue
+                }
+            }
+            "Z" | "z" => reply(self.toggle_breakpoint(command == "Z", args)),
+            "H" | "T" => reply("OK"),
+            "D" => Action::Close(Some("OK".to_string())),
+            "k" => Action::Close(None),
+            _ => self.query(packet),
+        }
+    }
+
+    /// the multi-letter packets
+    fn query(&mut self, packet: &str) -> Action {
+        let reply = |reply: &str| Action::Reply(reply.to_string());
+        if packet.starts_with("qSupported") {
+            Action::Reply(format!(
+                "PacketSize={:x};QStartNoAckMode+;qXfer:features:read+",
+                PACKET_SIZE
+            ))
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            reply("OK")
+        } else if packet == "qAttached" {
+            reply("1")
+        } else if packet == "qC" {
+            reply("QC1")
+        } else if packet == "qfThreadInfo" {
+            reply("m1")
+        } else if packet == "qsThreadInfo" {
+            reply("l")
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_range(args) {
+                Some((offset, len)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = start.saturating_add(len).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                    Action::Reply(format!("{}{}", more, &TARGET_XML[start..end]))
+                }
+                None => reply("E01"),
+            }
+        } else {
+            // an empty reply means unsupported
+            reply("")
+        }
+    }
+
+    fn registers(&self) -> String {
+        let bytes: Vec<u8> = (0..REGISTERS)
+            .flat_map(|number| self.register(number).to_le_bytes())
+            .collect();
+        encode_hex(&bytes)
+    }
+
+    fn register(&self, number: usize) -> u16 {
+        let cpu = self.machine.cpu();
+        match number {
+            0 => u16::from_be_bytes([cpu.a, cpu.psw()]),
+            1 => cpu.bc(),
+            2 => cpu.de(),
+            3 => cpu.hl(),
+            4 => cpu.sp,
+            _ => cpu.pc,
+        }
+    }
+
+    fn set_register(&mut self, number: usize, value: u16) {
+        let cpu = self.machine.cpu_mut();
+        match number {
+            0 => {
+                let [a, psw] = value.to_be_bytes();
+                cpu.a = a;
+                cpu.set_psw(psw);
+            }
+            1 => cpu.set_bc(value),
+            2 => cpu.set_de(value),
+            3 => cpu.set_hl(value),
+            4 => cpu.sp = value,
+            _ => cpu.pc = value,
+        }
+    }
+
+    /// `Z<type>,<addr>,<kind>` inserts and `z...` removes a breakpoint or watchpoint
+    fn toggle_breakpoint(&mut self, insert: bool, args: &str) -> &'static str {
+        let mut fields = args.split(',');
+        let parsed = (|| {
+            let kind = fields.next()?.parse::<u8>().ok()?;
+            let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+            let len = u16::from_str_radix(fields.next()?, 16).ok()?;
+            Some((kind, address, len))
+        })();
+        let (z_type, address, len) = match parsed {
+            Some(parsed) => parsed,
+            None => return "E01",
+        };
+        let kind = match z_type {
+            0 | 1 => Kind::Execute,
+            2 => Kind::Write,
+            3 => Kind::Read,
+            4 => Kind::Access,
+            _ => return "",
+        };
+        let breakpoints = &mut self.machine.cpu_mut().breakpoints;
+        let position = self
+            .inserted
+            .iter()
+            .position(|&(t, a, _)| t == z_type && a == address);
+        match (insert, position) {
+            // inserting twice must not add a second breakpoint
+            (true, Some(_)) => {}
+            (true, None) => {
+                let end = match kind {
+                    Kind::Execute => address,
+                    _ => address.wrapping_add(len.max(1) - 1),
+                };
+                let id = breakpoints.add(kind, address, end, None, 1);
+                self.inserted.push((z_type, address, id));
+            }
+            (false, Some(position)) => {
+                let (_, _, id) = self.inserted.remove(position);
+                breakpoints.remove(id);
+            }
+            (false, None) => return "E01",
+        }
+        "OK"
+    }
+
+    fn remove_all(&mut self) {
+        let breakpoints = &mut self.machine.cpu_mut().breakpoints;
+        for (_, _, id) in self.inserted.drain(..) {
+            breakpoints.remove(id);
+        }
+    }
+
+    fn step(&mut self) -> String {
+        self.machine.cpu_mut().take_hit();
+        self.machine.step();
+        match self.machine.cpu_mut().take_hit() {
+            Some(hit) => self.stop_reply(hit),
+            None => "S05".to_string(),
+        }
+    }
+
+    /// run until a breakpoint, the cpu halts with interrupts disabled or the client sends ^C
+    fn resume(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        self.machine.cpu_mut().take_hit();
+        loop {
+            for _ in 0..POLL_INTERVAL {
+                let cpu = self.machine.cpu();
+                if cpu.is_halted() && !cpu.interrupts_enabled() {
+                    return Ok("S05".to_string());
+                }
+                self.machine.step();
+                if let Some(hit) = self.machine.cpu_mut().take_hit() {
+                    return Ok(self.stop_reply(hit));
+                }
+            }
+            if interrupted(stream)? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    fn stop_reply(&self, hit: Hit) -> String {
+        let z_type = self
+            .inserted
+            .iter()
+            .find(|&&(_, _, id)| id == hit.id)
+            .map(|&(z_type, _, _)| z_type);
+        let watch = match (z_type, hit.event) {
+            (Some(4), _) => "awatch",
+            (_, Event::Write) => "watch",
+            (_, Event::Read) => "rwatch",
+            _ => return "S05".to_string(),
+        };
+        format!("T05{}:{:04x};", watch, hit.address)
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(byte[0])),
+        Err(err) if err.kind() == ErrorKind::ConnectionReset => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// true if the client sent a ^C, without blocking
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(1) => Ok(byte[0] == 0x03),
+        // a closed connection shows up at the next receive
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// undo the `}` escapes of binary data
+fn unescape(data: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+    while let Some(&byte) = iter.next() {
+        match byte {
+            b'}' => bytes.extend(iter.next().map(|byte| byte ^ 0x20)),
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `<addr>,<len>` in hex
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let (address, len) = text.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{checksum_of, GdbStub};
+    use crate::cpu::Cpu;
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, packet: &str) -> String {
+            write!(
+                self.stream,
+                "${}#{:02x}",
+                packet,
+                checksum_of(packet.as_bytes())
+            )
+            .unwrap();
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'+');
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'$');
+            let mut data = Vec::new();
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(),
+                checksum_of(&data)
+            );
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+    }
+
+    /// run a stub for `program` on a background thread, returns the connected client
+    /// and a handle that gives back the stub once the client detaches
+    fn connect(program: &[u8]) -> (Client, thread::JoinHandle<GdbStub<Cpu>>) {
+        let mut cpu = Cpu::new();
+        cpu.load(program, 0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stub = GdbStub::new(cpu);
+            let (stream, _) = listener.accept().unwrap();
+            stub.serve(stream).unwrap();
+            stub
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        (Client { stream }, server)
+    }
+
+    // LXI SP,0x2400; MVI A,0x42; STA 0x2000; loop: INR B; JMP loop
+    const PROGRAM: [u8; 12] = [
+        0x31, 0x00, 0x24, 0x3E, 0x42, 0x32, 0x00, 0x20, 0x04, 0xC3, 0x08, 0x00,
+    ];
+
+    #[test]
+    fn test_registers_and_memory() {
+        let (mut client, server) = connect(&PROGRAM);
+        assert!(client
+            .request("qSupported:swbreak+")
+            .starts_with("PacketSize=1000;"));
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("g"), "020000000000000000000000");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p5"), "0300");
+        assert_eq!(client.request("p4"), "0024");
+        assert_eq!(client.request("P3=3412"), "OK");
+        assert_eq!(client.request("m0,3"), "310024");
+        assert_eq!(client.request("M2000,2:abcd"), "OK");
+        assert_eq!(client.request("m2000,2"), "abcd");
+        assert_eq!(client.request("m0,801"), "E01");
+        assert_eq!(client.request("m0,ffffffffffffffff"), "E01");
+        assert_eq!(client.request("M0,801:00"), "E01");
+        assert_eq!(client.request("m0,800").len(), 0x1000);
+        // a first character that is more than a byte is just an unknown packet
+        assert_eq!(client.request("é"), "");
+        assert!(client
+            .request("qXfer:features:read:target.xml:0,1000")
+            .starts_with("l<?xml"));
+        assert_eq!(client.request("vMustReplyEmpty"), "");
+        assert_eq!(client.request("D"), "OK");
+
+        let stub = server.join().unwrap();
+        assert_eq!(stub.machine.hl(), 0x1234);
+        assert_eq!(stub.machine.read_byte(0x2001), 0xCD);
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let (mut client, server) = connect(&PROGRAM);
+        assert_eq!(client.request("Z2,2000,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:2000;");
+        assert_eq!(client.request("z2,2000,1"), "OK");
+        assert_eq!(client.request("Z0,8,1"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p5"), "0800");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p1"), "0002");
+        assert_eq!(client.request("z0,8,1"), "OK");
+        assert_eq!(client.request("z0,8,1"), "E01");
+
+        // ^C stops a continue with no breakpoints
+        client.stream.write_all(b"$c#63").unwrap();
+        let mut ack = [0];
+        client.stream.read_exact(&mut ack).unwrap();
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "S02");
+        assert_eq!(client.request("D"), "OK");
+
+        let stub = server.join().unwrap();
+        assert!(stub.machine.breakpoints.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{self, Write};
+
+/// writes an animated GIF that loops forever, a frame at a time, each frame a
+/// picture of palette indices
+pub struct Gif<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    /// log2 of the palette size
+    bits: u8,
+    previous: Option<Vec<u8>>,
+}
+
+impl<W: Write> Gif<W> {
+    /// write the header, `palette` has up to 256 colours and the first is the
+    /// background
+    pub fn new(mut writer: W, width: u16, height: u16, palette: &[[u8; 3]]) -> io::Result<Self> {
+        assert!(!palette.is_empty() && palette.len() <= 256);
+        let bits = (palette.len().next_power_of_two().trailing_zeros() as u8).max(1);
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // a global palette with `bits` bits of colour, background 0, square pixels
+        writer.write_all(&[0x80 | (bits - 1) << 4 | (bits - 1), 0, 0])?;
+        for i in 0..1 << bits {
+            writer.write_all(&palette.get(i).copied().unwrap_or([0; 3]))?;
+        }
+        // the application extension browsers read the loop count from, 0 for
+        // forever
+        writer.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(Gif {
+            writer,
+            width: width as usize,
+            height: height as usize,
+            bits,
+            previous: None,
+        })
+    }
+
+    /// add a frame shown for `delay` hundredths of a second, storing only the
+    /// rectangle that changed since the last one
+    pub fn frame(&mut self, pixels: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(pixels.len(), self.width * self.height);
+        let (left, top, width, height) = match &self.previous {
+            Some(previous) => changed(previous, pixels, self.width)
+                // nothing changed, but the delay still needs a frame
+                .unwrap_or((0, 0, 1, 1)),
+            None => (0, 0, self.width, self.height),
+        };
+
+        // graphic control extension: leave the frame in place for the next to
+        // draw over, no transparency
+        self.writer.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+
+        self.writer.write_all(&[0x2C])?;
+        for value in [left, top, width, height] {
+            self.writer.write_all(&(value as u16).to_le_bytes())?;
+        }
+        self.writer.write_all(&[0])?;
+
+        // codes start a bit wider than the indices, and at least 3 bits
+        let min_size = self.bits.max(2);
+        let rows = pixels.chunks(self.width).skip(top).take(height);
+        let data = lzw(rows.flat_map(|row| &row[left..left + width]), min_size);
+        self.writer.write_all(&[min_size])?;
+        for block in data.chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])?;
+
+        self.previous = Some(pixels.to_vec());
+        Ok(())
+    }
+
+    /// write the trailer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// the smallest rectangle, left, top, width and height, holding every pixel
+/// that differs, or none if they are the same
+fn changed(before: &[u8], after: &[u8], width: usize) -> Option<(usize, usize, usize, usize)> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (y, (old, new)) in before.chunks(width).zip(after.chunks(width)).enumerate() {
+        let first = match old.iter().zip(new).position(|(a, b)| a != b) {
+            Some(first) => first,
+            None => continue,
+        };
+        let last = old.iter().zip(new).rposition(|(a, b)| a != b).unwrap();
+        bounds = Some(match bounds {
+            Some((left, top, right, _)) => (left.min(first), top, right.max(last), y),
+            None => (first, y, last, y),
+        });
+    }
+    bounds.map(|(left, top, right, bottom)| (left, top, right - left + 1, bottom - top + 1))
+}
+
+/// GIF's variable width LZW, codes packed from the low bit up, starting with a
+/// clear and starting over with another whenever the 4096 codes run out
+fn lzw<'a>(indices: impl IntoIterator<Item = &'a u8>, min_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_size;
+    let end = clear + 1;
+    let symbols = clear as usize;
+
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut buffered = 0;
+    let mut emit = |code: u16, width: u8, out: &mut Vec<u8>| {
+        buffer |= (code as u32) << buffered;
+        buffered += width;
+        while buffered >= 8 {
+            out.push(buffer as u8);
+            buffer >>= 8;
+            buffered -= 8;
+        }
+    };
+
+    // the code for a string followed by each symbol, 0 for none yet, which no
+    // string code can be
+    let mut table = vec![0u16; 4096 * symbols];
+    let mut next = end + 1;
+    let mut width = min_size + 1;
+    emit(clear, width, &mut out);
+
+    let mut indices = indices.into_iter();
+    if let Some(&first) = indices.next() {
+        let mut prefix = first as u16;
+        for &index in indices {
+            let slot = prefix as usize * symbols + index as usize;
+            if table[slot] != 0 {
+                prefix = table[slot];
+                continue;
+            }
+            emit(prefix, width, &mut out);
+            if next < 4096 {
+                table[slot] = next;
+                next += 1;
+                if next > 1 << width && width < 12 {
+                    width += 1;
+                }
+            } else {
+                emit(clear, width, &mut out);
+                table.fill(0);
+                next = end + 1;
+                width = min_size + 1;
+            }
+            prefix = index as u16;
+        }
+        emit(prefix, width, &mut out);
+    }
+    emit(end, width, &mut out);
+    if buffered > 0 {
+        out.push(buffer as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed, lzw, Gif};
+
+    /// the other half of `lzw`
+    fn unlzw(data: &[u8], min_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_size;
+        let mut strings: Vec<Vec<u8>> = Vec::new();
+        let mut width = min_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut out = Vec::new();
+        let mut bit = 0;
+        loop {
+            let mut code = 0;
+            for i in 0..width as usize {
+                let byte = data[(bit + i) / 8];
+                code |= ((byte >> ((bit + i) % 8)) as usize & 1) << i;
+            }
+            bit += width as usize;
+            if code == clear {
+                strings = (0..clear).map(|i| vec![i as u8]).collect();
+                strings.extend([vec![], vec![]]);
+                width = min_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let string = match (strings.get(code), previous) {
+                (Some(string), _) => string.clone(),
+                // the string being defined by this very code
+                (None, Some(previous)) => {
+                    let mut string = strings[previous].clone();
+                    string.push(string[0]);
+                    string
+                }
+                (None, None) => panic!("code {} before it is defined", code),
+            };
+            if let Some(previous) = previous {
+                if strings.len() < 4096 {
+                    let mut added = strings[previous].clone();
+                    added.push(string[0]);
+                    strings.push(added);
+                }
+            }
+            if strings.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+            out.extend(&string);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        // long and varied enough to fill the table a few times over
+        let mut state = 1u32;
+        let indices: Vec<u8> = (0..200_000)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                if i % 1000 < 700 {
+                    0
+                } else {
+                    (state % 4) as u8
+                }
+            })
+            .collect();
+        for min_size in [2, 8] {
+            assert_eq!(unlzw(&lzw(&indices, min_size), min_size), indices);
+        }
+        assert_eq!(unlzw(&lzw(&[], 2), 2), []);
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut before = vec![0; 16 * 8];
+        let mut after = before.clone();
+        after[2 * 16 + 3] = 1;
+        after[5 * 16 + 9] = 2;
+        assert_eq!(changed(&before, &after, 16), Some((3, 2, 7, 4)));
+        assert_eq!(changed(&before, &before, 16), None);
+
+        let palette = [[0, 0, 0], [0xFF, 0xFF, 0xFF], [0xFF, 0, 0]];
+        let mut gif = Gif::new(Vec::new(), 16, 8, &palette).unwrap();
+        gif.frame(&before, 3).unwrap();
+        gif.frame(&after, 3).unwrap();
+        before.copy_from_slice(&after);
+        gif.frame(&before, 4).unwrap();
+        let data = gif.finish().unwrap();
+        assert!(data.starts_with(b"GIF89a\x10\x00\x08\x00\x91\x00\x00"));
+        assert_eq!(data[13..25], [0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0]);
+        assert_eq!(data.last(), Some(&0x3B));
+        // the second frame only holds the changed rectangle
+        let descriptors: Vec<&[u8]> = data
+            .windows(10)
+            .filter(|window| window[0] == 0x2C && window[9] == 0)
+            .collect();
+        assert_eq!(descriptors[1], [0x2C, 3, 0, 2, 0, 7, 0, 4, 0, 0]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+
+use crate::{callstack::Frame, cpu::CpuState};
+
+/// what one instruction or interrupt changed, enough to undo it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// registers, flags and counters before the change
+    pub state: CpuState,
+    /// true for an interrupt rather than an instruction
+    pub interrupt: bool,
+    /// (address, old value, new value) of each memory write, in the order written
+    pub writes: Vec<(u16, u8, u8)>,
+    /// length of the shadow call stack before
+    pub(crate) frames: usize,
+    /// shadow call stack frames removed by a return
+    pub(crate) removed: Vec<Frame>,
+    /// the outermost shadow call stack frame a call dropped at the limit
+    pub(crate) dropped: Option<Frame>,
+    /// whether a return added a mismatch to the shadow call stack
+    pub(crate) mismatch: bool,
+}
+
+/// the most recent changes made by the cpu, newest last, so execution can be stepped
+/// backwards, disabled with a capacity of 0
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    /// keep at most `capacity` entries, dropping the oldest
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// the newest entry that wrote `address`, with the value it overwrote
+    pub fn last_write(&self, address: u16) -> Option<(&Entry, u8)> {
+        self.entries.iter().rev().find_map(|entry| {
+            entry
+                .writes
+                .iter()
+                .rev()
+                .find(|&&(written, _, _)| written == address)
+                .map(|&(_, old, _)| (entry, old))
+        })
+    }
+
+    pub(crate) fn begin(&mut self, state: CpuState, interrupt: bool, frames: usize) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            state,
+            interrupt,
+            writes: Vec::new(),
+            frames,
+            removed: Vec::new(),
+            dropped: None,
+            mismatch: false,
+        });
+    }
+
+    pub(crate) fn current(&mut self) -> Option<&mut Entry> {
+        self.entries.back_mut()
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{callstack::MAX_FRAMES, cpu::Cpu};
+
+    // LXI H,0x2000; MVI M,0x11; INR M; CALL 0x000A; HLT; MVI M,0x33; RET
+    const PROGRAM: [u8; 14] = [
+        0x21, 0x00, 0x20, 0x36, 0x11, 0x34, 0xCD, 0x0A, 0x00, 0x76, 0x36, 0x33, 0xC9, 0x00,
+    ];
+
+    fn start() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load(&PROGRAM, 0);
+        cpu.sp = 0x2400;
+        cpu.history.set_capacity(100);
+        cpu
+    }
+
+    #[test]
+    fn test_step_back_restores_everything() {
+        let mut cpu = start();
+        let mut states = vec![cpu.state()];
+        for _ in 0..6 {
+            cpu.step();
+            states.push(cpu.state());
+        }
+        assert_eq!(cpu.read_byte(0x2000), 0x33);
+        assert!(cpu.call_stack.frames().is_empty());
+        assert_eq!(cpu.call_stack.mismatches().count(), 0);
+
+        // back to just inside the call, with its frame and return address restored
+        for _ in 0..2 {
+            assert!(cpu.step_back().is_some());
+        }
+        assert_eq!(cpu.state(), states[4]);
+        assert_eq!(cpu.read_byte(0x2000), 0x12);
+        assert_eq!(cpu.call_stack.frames().len(), 1);
+
+        while cpu.step_back().is_some() {}
+        assert_eq!(cpu.state(), states[0]);
+        assert_eq!(cpu.read_byte(0x2000), 0x00);
+        assert_eq!(cpu.read_byte(0x23FE), 0x00);
+        assert!(cpu.call_stack.frames().is_empty());
+    }
+
+    #[test]
+    fn test_last_write_and_capacity() {
+        let mut cpu = start();
+        for _ in 0..6 {
+            cpu.step();
+        }
+        let (entry, old) = cpu.history.last_write(0x2000).unwrap();
+        assert_eq!(entry.state.pc, 0x000A);
+        assert_eq!(old, 0x12);
+        assert_eq!(cpu.history.last_write(0x23FE).unwrap().0.state.pc, 0x0006);
+        assert!(cpu.history.last_write(0x2001).is_none());
+
+        cpu.history.set_capacity(2);
+        assert_eq!(cpu.history.len(), 2);
+        assert!(cpu.history.last_write(0x23FE).is_none());
+
+        // disabled history records nothing
+        cpu.history.set_capacity(0);
+        cpu.step();
+        assert!(cpu.history.is_empty());
+        assert!(cpu.step_back().is_none());
+    }
+
+    #[test]
+    fn test_step_back_across_the_frame_limit() {
+        // CALL 0x0000, recursing until the shadow call stack drops its outermost frames
+        let mut cpu = Cpu::new();
+        cpu.load(&[0xCD, 0x00, 0x00], 0);
+        cpu.history.set_capacity(MAX_FRAMES + 10);
+        let mut stacks = Vec::new();
+        for _ in 0..MAX_FRAMES + 5 {
+            stacks.push(cpu.call_stack.frames().clone());
+            cpu.step();
+        }
+        assert_eq!(cpu.call_stack.frames().len(), MAX_FRAMES);
+        assert_eq!(cpu.call_stack.frames()[0].sp, 0xFFFE - 2 * 5);
+
+        for expected in stacks.iter().rev() {
+            assert!(cpu.step_back().is_some());
+            assert_eq!(cpu.call_stack.frames(), expected);
+        }
+        assert!(cpu.call_stack.frames().is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+use std::fmt;
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     NOP,            // 0x00
     LXI_B_D16(u16), // 0x01
@@ -276,16 +278,368 @@ pub enum Instruction {
     CM_ADR(u16),
 
     CPI_D8(u8),
-    RST_7
+    RST_7,
 }
 
 impl Instruction {
-    // todo: error handling
-    /// returns an `Instruction` along with it's size
-    pub fn disassemble(data: &[u8], pc: usize) -> (Self, usize) {
-        let d8 = data[pc + 1];
-        let d16 = ((data[pc + 2] as u16) << 8) | data[pc + 1] as u16;
-        match data[pc] {
+    /// number of clock cycles the instruction takes, conditional calls and returns
+    /// take 6 more cycles when the condition is met
+    pub fn cycles(&self) -> u64 {
+        use Instruction::*;
+        match self {
+            NOP | RLC | RRC | RAL | RAR | DAA | CMA | STC | CMC | ADD_B | ADD_C | ADD_D | ADD_E
+            | ADD_H | ADD_L | ADD_A | ADC_B | ADC_C | ADC_D | ADC_E | ADC_H | ADC_L | ADC_A
+            | SUB_B | SUB_C | SUB_D | SUB_E | SUB_H | SUB_L | SUB_A | SBB_B | SBB_C | SBB_D
+            | SBB_E | SBB_H | SBB_L | SBB_A | ANA_B | ANA_C | ANA_D | ANA_E | ANA_H | ANA_L
+            | ANA_A | XRA_B | XRA_C | XRA_D | XRA_E | XRA_H | XRA_L | XRA_A | ORA_B | ORA_C
+            | ORA_D | ORA_E | ORA_H | ORA_L | ORA_A | CMP_B | CMP_C | CMP_D | CMP_E | CMP_H
+            | CMP_L | CMP_A | XCHG | DI | EI => 4,
+            INX_B | INR_B | DCR_B | DCX_B | INR_C | DCR_C | INX_D | INR_D | DCR_D | DCX_D
+            | INR_E | DCR_E | INX_H | INR_H | DCR_H | DCX_H | INR_L | DCR_L | INX_SP | DCX_SP
+            | INR_A | DCR_A | MOV_B_B | MOV_B_C | MOV_B_D | MOV_B_E | MOV_B_H | MOV_B_L
+            | MOV_B_A | MOV_C_B | MOV_C_C | MOV_C_D | MOV_C_E | MOV_C_H | MOV_C_L | MOV_C_A
+            | MOV_D_B | MOV_D_C | MOV_D_D | MOV_D_E | MOV_D_H | MOV_D_L | MOV_D_A | MOV_E_B
+            | MOV_E_C | MOV_E_D | MOV_E_E | MOV_E_H | MOV_E_L | MOV_E_A | MOV_H_B | MOV_H_C
+            | MOV_H_D | MOV_H_E | MOV_H_H | MOV_H_L | MOV_H_A | MOV_L_B | MOV_L_C | MOV_L_D
+            | MOV_L_E | MOV_L_H | MOV_L_L | MOV_L_A | MOV_A_B | MOV_A_C | MOV_A_D | MOV_A_E
+            | MOV_A_H | MOV_A_L | MOV_A_A | RNZ | RZ | RNC | RC | RPO | RPE | PCHL | RP | RM
+            | SPHL => 5,
+            STAX_B | MVI_B_D8(_) | LDAX_B | MVI_C_D8(_) | STAX_D | MVI_D_D8(_) | LDAX_D
+            | MVI_E_D8(_) | MVI_H_D8(_) | MVI_L_D8(_) | MVI_A_D8(_) | MOV_B_M | MOV_C_M
+            | MOV_D_M | MOV_E_M | MOV_H_M | MOV_L_M | MOV_M_B | MOV_M_C | MOV_M_D | MOV_M_E
+            | MOV_M_H | MOV_M_L | HLT | MOV_M_A | MOV_A_M | ADD_M | ADC_M | SUB_M | SBB_M
+            | ANA_M | XRA_M | ORA_M | CMP_M | ADI_D8(_) | ACI_D8(_) | SUI_D8(_) | SBI_D8(_)
+            | ANI_D8(_) | XRI_D8(_) | ORI_D8(_) | CPI_D8(_) => 7,
+            LXI_B_D16(_) | DAD_B | LXI_D_D16(_) | DAD_D | LXI_H_D16(_) | DAD_H | LXI_SP_D16(_)
+            | INR_M | DCR_M | MVI_M_D8(_) | DAD_SP | POP_B | JNZ_ADR(_) | JMP_ADR(_) | RET
+            | JZ_ADR(_) | POP_D | JNC_ADR(_) | OUT_D8(_) | JC_ADR(_) | IN_D8(_) | POP_H
+            | JPO_ADR(_) | JPE_ADR(_) | POP_PSW | JP_ADR(_) | JM_ADR(_) => 10,
+            CNZ_ADR(_) | PUSH_B | RST_0 | CZ_ADR(_) | RST_1 | CNC_ADR(_) | PUSH_D | RST_2
+            | CC_ADR(_) | RST_3 | CPO_ADR(_) | PUSH_H | RST_4 | CPE_ADR(_) | RST_5 | CP_ADR(_)
+            | PUSH_PSW | RST_6 | CM_ADR(_) | RST_7 => 11,
+            STA_ADR(_) | LDA_ADR(_) => 13,
+            SHLD_ADR(_) | LHLD_ADR(_) => 16,
+            CALL_ADR(_) => 17,
+            XTHL => 18,
+        }
+    }
+
+    /// size of the instruction in bytes, including operands
+    pub fn size(&self) -> usize {
+        use Instruction::*;
+        match self {
+            LXI_B_D16(_) | LXI_D_D16(_) | LXI_H_D16(_) | SHLD_ADR(_) | LHLD_ADR(_)
+            | LXI_SP_D16(_) | STA_ADR(_) | LDA_ADR(_) | JNZ_ADR(_) | JMP_ADR(_) | CNZ_ADR(_)
+            | JZ_ADR(_) | CZ_ADR(_) | CALL_ADR(_) | JNC_ADR(_) | CNC_ADR(_) | JC_ADR(_)
+            | CC_ADR(_) | JPO_ADR(_) | CPO_ADR(_) | JPE_ADR(_) | CPE_ADR(_) | JP_ADR(_)
+            | CP_ADR(_) | JM_ADR(_) | CM_ADR(_) => 3,
+            MVI_B_D8(_) | MVI_C_D8(_) | MVI_D_D8(_) | MVI_E_D8(_) | MVI_H_D8(_) | MVI_L_D8(_)
+            | MVI_M_D8(_) | MVI_A_D8(_) | ADI_D8(_) | ACI_D8(_) | OUT_D8(_) | SUI_D8(_)
+            | IN_D8(_) | SBI_D8(_) | ANI_D8(_) | XRI_D8(_) | ORI_D8(_) | CPI_D8(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// the 8 bit operand, an immediate value or a port
+    pub fn operand8(&self) -> Option<u8> {
+        use Instruction::*;
+        match *self {
+            MVI_B_D8(x) | MVI_C_D8(x) | MVI_D_D8(x) | MVI_E_D8(x) | MVI_H_D8(x) | MVI_L_D8(x)
+            | MVI_M_D8(x) | MVI_A_D8(x) | ADI_D8(x) | ACI_D8(x) | OUT_D8(x) | SUI_D8(x)
+            | IN_D8(x) | SBI_D8(x) | ANI_D8(x) | XRI_D8(x) | ORI_D8(x) | CPI_D8(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// the 16 bit operand, an address for jumps, calls and direct loads and stores
+    pub fn operand16(&self) -> Option<u16> {
+        use Instruction::*;
+        match *self {
+            LXI_B_D16(x) | LXI_D_D16(x) | LXI_H_D16(x) | SHLD_ADR(x) | LHLD_ADR(x)
+            | LXI_SP_D16(x) | STA_ADR(x) | LDA_ADR(x) | JNZ_ADR(x) | JMP_ADR(x) | CNZ_ADR(x)
+            | JZ_ADR(x) | CZ_ADR(x) | CALL_ADR(x) | JNC_ADR(x) | CNC_ADR(x) | JC_ADR(x)
+            | CC_ADR(x) | JPO_ADR(x) | CPO_ADR(x) | JPE_ADR(x) | CPE_ADR(x) | JP_ADR(x)
+            | CP_ADR(x) | JM_ADR(x) | CM_ADR(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// the opcode byte, for undocumented aliases that of the documented instruction
+    pub fn opcode(&self) -> u8 {
+        use Instruction::*;
+        match self {
+            NOP => 0x00,
+            LXI_B_D16(_) => 0x01,
+            STAX_B => 0x02,
+            INX_B => 0x03,
+            INR_B => 0x04,
+            DCR_B => 0x05,
+            MVI_B_D8(_) => 0x06,
+            RLC => 0x07,
+            DAD_B => 0x09,
+            LDAX_B => 0x0A,
+            DCX_B => 0x0B,
+            INR_C => 0x0C,
+            DCR_C => 0x0D,
+            MVI_C_D8(_) => 0x0E,
+            RRC => 0x0F,
+            LXI_D_D16(_) => 0x11,
+            STAX_D => 0x12,
+            INX_D => 0x13,
+            INR_D => 0x14,
+            DCR_D => 0x15,
+            MVI_D_D8(_) => 0x16,
+            RAL => 0x17,
+            DAD_D => 0x19,
+            LDAX_D => 0x1A,
+            DCX_D => 0x1B,
+            INR_E => 0x1C,
+            DCR_E => 0x1D,
+            MVI_E_D8(_) => 0x1E,
+            RAR => 0x1F,
+            LXI_H_D16(_) => 0x21,
+            SHLD_ADR(_) => 0x22,
+            INX_H => 0x23,
+            INR_H => 0x24,
+            DCR_H => 0x25,
+            MVI_H_D8(_) => 0x26,
+            DAA => 0x27,
+            DAD_H => 0x29,
+            LHLD_ADR(_) => 0x2A,
+            DCX_H => 0x2B,
+            INR_L => 0x2C,
+            DCR_L => 0x2D,
+            MVI_L_D8(_) => 0x2E,
+            CMA => 0x2F,
+            LXI_SP_D16(_) => 0x31,
+            STA_ADR(_) => 0x32,
+            INX_SP => 0x33,
+            INR_M => 0x34,
+            DCR_M => 0x35,
+            MVI_M_D8(_) => 0x36,
+            STC => 0x37,
+            DAD_SP => 0x39,
+            LDA_ADR(_) => 0x3A,
+            DCX_SP => 0x3B,
+            INR_A => 0x3C,
+            DCR_A => 0x3D,
+            MVI_A_D8(_) => 0x3E,
+            CMC => 0x3F,
+            MOV_B_B => 0x40,
+            MOV_B_C => 0x41,
+            MOV_B_D => 0x42,
+            MOV_B_E => 0x43,
+            MOV_B_H => 0x44,
+            MOV_B_L => 0x45,
+            MOV_B_M => 0x46,
+            MOV_B_A => 0x47,
+            MOV_C_B => 0x48,
+            MOV_C_C => 0x49,
+            MOV_C_D => 0x4A,
+            MOV_C_E => 0x4B,
+            MOV_C_H => 0x4C,
+            MOV_C_L => 0x4D,
+            MOV_C_M => 0x4E,
+            MOV_C_A => 0x4F,
+            MOV_D_B => 0x50,
+            MOV_D_C => 0x51,
+            MOV_D_D => 0x52,
+            MOV_D_E => 0x53,
+            MOV_D_H => 0x54,
+            MOV_D_L => 0x55,
+            MOV_D_M => 0x56,
+            MOV_D_A => 0x57,
+            MOV_E_B => 0x58,
+            MOV_E_C => 0x59,
+            MOV_E_D => 0x5A,
+            MOV_E_E => 0x5B,
+            MOV_E_H => 0x5C,
+            MOV_E_L => 0x5D,
+            MOV_E_M => 0x5E,
+            MOV_E_A => 0x5F,
+            MOV_H_B => 0x60,
+            MOV_H_C => 0x61,
+            MOV_H_D => 0x62,
+            MOV_H_E => 0x63,
+            MOV_H_H => 0x64,
+            MOV_H_L => 0x65,
+            MOV_H_M => 0x66,
+            MOV_H_A => 0x67,
+            MOV_L_B => 0x68,
+            MOV_L_C => 0x69,
+            MOV_L_D => 0x6A,
+            MOV_L_E => 0x6B,
+            MOV_L_H => 0x6C,
+            MOV_L_L => 0x6D,
+            MOV_L_M => 0x6E,
+            MOV_L_A => 0x6F,
+            MOV_M_B => 0x70,
+            MOV_M_C => 0x71,
+            MOV_M_D => 0x72,
+            MOV_M_E => 0x73,
+            MOV_M_H => 0x74,
+            MOV_M_L => 0x75,
+            HLT => 0x76,
+            MOV_M_A => 0x77,
+            MOV_A_B => 0x78,
+            MOV_A_C => 0x79,
+            MOV_A_D => 0x7A,
+            MOV_A_E => 0x7B,
+            MOV_A_H => 0x7C,
+            MOV_A_L => 0x7D,
+            MOV_A_M => 0x7E,
+            MOV_A_A => 0x7F,
+            ADD_B => 0x80,
+            ADD_C => 0x81,
+            ADD_D => 0x82,
+            ADD_E => 0x83,
+            ADD_H => 0x84,
+            ADD_L => 0x85,
+            ADD_M => 0x86,
+            ADD_A => 0x87,
+            ADC_B => 0x88,
+            ADC_C => 0x89,
+            ADC_D => 0x8A,
+            ADC_E => 0x8B,
+            ADC_H => 0x8C,
+            ADC_L => 0x8D,
+            ADC_M => 0x8E,
+            ADC_A => 0x8F,
+            SUB_B => 0x90,
+            SUB_C => 0x91,
+            SUB_D => 0x92,
+            SUB_E => 0x93,
+            SUB_H => 0x94,
+            SUB_L => 0x95,
+            SUB_M => 0x96,
+            SUB_A => 0x97,
+            SBB_B => 0x98,
+            SBB_C => 0x99,
+            SBB_D => 0x9A,
+            SBB_E => 0x9B,
+            SBB_H => 0x9C,
+            SBB_L => 0x9D,
+            SBB_M => 0x9E,
+            SBB_A => 0x9F,
+            ANA_B => 0xA0,
+            ANA_C => 0xA1,
+            ANA_D => 0xA2,
+            ANA_E => 0xA3,
+            ANA_H => 0xA4,
+            ANA_L => 0xA5,
+            ANA_M => 0xA6,
+            ANA_A => 0xA7,
+            XRA_B => 0xA8,
+            XRA_C => 0xA9,
+            XRA_D => 0xAA,
+            XRA_E => 0xAB,
+            XRA_H => 0xAC,
+            XRA_L => 0xAD,
+            XRA_M => 0xAE,
+            XRA_A => 0xAF,
+            ORA_B => 0xB0,
+            ORA_C => 0xB1,
+            ORA_D => 0xB2,
+            ORA_E => 0xB3,
+            ORA_H => 0xB4,
+            ORA_L => 0xB5,
+            ORA_M => 0xB6,
+            ORA_A => 0xB7,
+            CMP_B => 0xB8,
+            CMP_C => 0xB9,
+            CMP_D => 0xBA,
+            CMP_E => 0xBB,
+            CMP_H => 0xBC,
+            CMP_L => 0xBD,
+            CMP_M => 0xBE,
+            CMP_A => 0xBF,
+            RNZ => 0xC0,
+            POP_B => 0xC1,
+            JNZ_ADR(_) => 0xC2,
+            JMP_ADR(_) => 0xC3,
+            CNZ_ADR(_) => 0xC4,
+            PUSH_B => 0xC5,
+            ADI_D8(_) => 0xC6,
+            RST_0 => 0xC7,
+            RZ => 0xC8,
+            RET => 0xC9,
+            JZ_ADR(_) => 0xCA,
+            CZ_ADR(_) => 0xCC,
+            CALL_ADR(_) => 0xCD,
+            ACI_D8(_) => 0xCE,
+            RST_1 => 0xCF,
+            RNC => 0xD0,
+            POP_D => 0xD1,
+            JNC_ADR(_) => 0xD2,
+            OUT_D8(_) => 0xD3,
+            CNC_ADR(_) => 0xD4,
+            PUSH_D => 0xD5,
+            SUI_D8(_) => 0xD6,
+            RST_2 => 0xD7,
+            RC => 0xD8,
+            JC_ADR(_) => 0xDA,
+            IN_D8(_) => 0xDB,
+            CC_ADR(_) => 0xDC,
+            SBI_D8(_) => 0xDE,
+            RST_3 => 0xDF,
+            RPO => 0xE0,
+            POP_H => 0xE1,
+            JPO_ADR(_) => 0xE2,
+            XTHL => 0xE3,
+            CPO_ADR(_) => 0xE4,
+            PUSH_H => 0xE5,
+            ANI_D8(_) => 0xE6,
+            RST_4 => 0xE7,
+            RPE => 0xE8,
+            PCHL => 0xE9,
+            JPE_ADR(_) => 0xEA,
+            XCHG => 0xEB,
+            CPE_ADR(_) => 0xEC,
+            XRI_D8(_) => 0xEE,
+            RST_5 => 0xEF,
+            RP => 0xF0,
+            POP_PSW => 0xF1,
+            JP_ADR(_) => 0xF2,
+            DI => 0xF3,
+            CP_ADR(_) => 0xF4,
+            PUSH_PSW => 0xF5,
+            ORI_D8(_) => 0xF6,
+            RST_6 => 0xF7,
+            RM => 0xF8,
+            SPHL => 0xF9,
+            JM_ADR(_) => 0xFA,
+            EI => 0xFB,
+            CM_ADR(_) => 0xFC,
+            CPI_D8(_) => 0xFE,
+            RST_7 => 0xFF,
+        }
+    }
+
+    /// the bytes of the instruction, opcode first and operands little endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode()];
+        match (self.size(), self.operand8(), self.operand16()) {
+            (2, Some(d8), _) => bytes.push(d8),
+            (3, _, Some(d16)) => bytes.extend_from_slice(&d16.to_le_bytes()),
+            _ => {}
+        }
+        bytes
+    }
+
+    /// decode the instruction at `pc` in `data`, returns it along with its size, or
+    /// `None` if `pc` is past the end or the operands would run off the end
+    ///
+    /// the undocumented opcodes decode as the instructions they alias on the 8080:
+    /// 0x08 to 0x38 as NOP, 0xCB as JMP, 0xD9 as RET and 0xDD, 0xED and 0xFD as CALL
+    pub fn disassemble(data: &[u8], pc: usize) -> Option<(Self, usize)> {
+        let opcode = *data.get(pc)?;
+        if data.len() - pc < length(opcode) {
+            return None;
+        }
+        let byte = |i: usize| data.get(pc + i).copied().unwrap_or(0);
+        let d8 = byte(1);
+        let d16 = ((byte(2) as u16) << 8) | byte(1) as u16;
+        let decoded = match opcode {
             0x00 => (Instruction::NOP, 1),
             0x01 => (Instruction::LXI_B_D16(d16), 3),
             0x02 => (Instruction::STAX_B, 1),
@@ -426,8 +780,8 @@ impl Instruction {
             0x7E => (Instruction::MOV_A_M, 1),
             0x7F => (Instruction::MOV_A_A, 1),
 
-            0x80 => (Instruction::ADD_C, 1),
-            0x81 => (Instruction::ADD_B, 1),
+            0x80 => (Instruction::ADD_B, 1),
+            0x81 => (Instruction::ADD_C, 1),
             0x82 => (Instruction::ADD_D, 1),
             0x83 => (Instruction::ADD_E, 1),
             0x84 => (Instruction::ADD_H, 1),
@@ -563,7 +917,302 @@ impl Instruction {
             0xFE => (Instruction::CPI_D8(d8), 2),
             0xFF => (Instruction::RST_7, 1),
 
-            _ => panic!("unknown instruction while disassembling: {:#04X}", data[pc]),
+            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => (Instruction::NOP, 1),
+            0xCB => (Instruction::JMP_ADR(d16), 3),
+            0xD9 => (Instruction::RET, 1),
+            0xDD | 0xED | 0xFD => (Instruction::CALL_ADR(d16), 3),
+        };
+        Some(decoded)
+    }
+}
+
+/// size of the instruction an opcode starts, including operands
+fn length(opcode: u8) -> usize {
+    match opcode {
+        // LXI, SHLD, LHLD, STA and LDA
+        0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2A | 0x32 | 0x3A => 3,
+        // MVI
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        // Jcc, JMP, Ccc, CALL and their undocumented aliases
+        0xC0..=0xFF if opcode & 0x07 == 0x02 || opcode & 0x07 == 0x04 => 3,
+        0xC3 | 0xCB | 0xCD | 0xDD | 0xED | 0xFD => 3,
+        // immediate arithmetic, OUT and IN
+        0xC0..=0xFF if opcode & 0x07 == 0x06 => 2,
+        0xD3 | 0xDB => 2,
+        _ => 1,
+    }
+}
+
+/// every instruction in `data`, as (address, size, instruction) with addresses
+/// counted from `origin`, bytes at the end too few for their instruction come
+/// last with no instruction
+pub fn disassemble_all(data: &[u8], origin: u16) -> Vec<(u16, usize, Option<Instruction>)> {
+    let mut lines = Vec::new();
+    let mut pc = 0;
+    while pc < data.len() {
+        let address = origin.wrapping_add(pc as u16);
+        match Instruction::disassemble(data, pc) {
+            Some((instruction, size)) => {
+                lines.push((address, size, Some(instruction)));
+                pc += size;
+            }
+            None => {
+                lines.push((address, data.len() - pc, None));
+                break;
+            }
+        }
+    }
+    lines
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        match self {
+            NOP => write!(f, "NOP"),
+            LXI_B_D16(x) => write!(f, "LXI B,{:#06X}", x),
+            STAX_B => write!(f, "STAX B"),
+            INX_B => write!(f, "INX B"),
+            INR_B => write!(f, "INR B"),
+            DCR_B => write!(f, "DCR B"),
+            MVI_B_D8(x) => write!(f, "MVI B,{:#04X}", x),
+            RLC => write!(f, "RLC"),
+            DAD_B => write!(f, "DAD B"),
+            LDAX_B => write!(f, "LDAX B"),
+            DCX_B => write!(f, "DCX B"),
+            INR_C => write!(f, "INR C"),
+            DCR_C => write!(f, "DCR C"),
+            MVI_C_D8(x) => write!(f, "MVI C,{:#04X}", x),
+            RRC => write!(f, "RRC"),
+            LXI_D_D16(x) => write!(f, "LXI D,{:#06X}", x),
+            STAX_D => write!(f, "STAX D"),
+            INX_D => write!(f, "INX D"),
+            INR_D => write!(f, "INR D"),
+            DCR_D => write!(f, "DCR D"),
+            MVI_D_D8(x) => write!(f, "MVI D,{:#04X}", x),
+            RAL => write!(f, "RAL"),
+            DAD_D => write!(f, "DAD D"),
+            LDAX_D => write!(f, "LDAX D"),
+            DCX_D => write!(f, "DCX D"),
+            INR_E => write!(f, "INR E"),
+            DCR_E => write!(f, "DCR E"),
+            MVI_E_D8(x) => write!(f, "MVI E,{:#04X}", x),
+            RAR => write!(f, "RAR"),
+            LXI_H_D16(x) => write!(f, "LXI H,{:#06X}", x),
+            SHLD_ADR(x) => write!(f, "SHLD {:#06X}", x),
+            INX_H => write!(f, "INX H"),
+            INR_H => write!(f, "INR H"),
+            DCR_H => write!(f, "DCR H"),
+            MVI_H_D8(x) => write!(f, "MVI H,{:#04X}", x),
+            DAA => write!(f, "DAA"),
+            DAD_H => write!(f, "DAD H"),
+            LHLD_ADR(x) => write!(f, "LHLD {:#06X}", x),
+            DCX_H => write!(f, "DCX H"),
+            INR_L => write!(f, "INR L"),
+            DCR_L => write!(f, "DCR L"),
+            MVI_L_D8(x) => write!(f, "MVI L,{:#04X}", x),
+            CMA => write!(f, "CMA"),
+            LXI_SP_D16(x) => write!(f, "LXI SP,{:#06X}", x),
+            STA_ADR(x) => write!(f, "STA {:#06X}", x),
+            INX_SP => write!(f, "INX SP"),
+            INR_M => write!(f, "INR M"),
+            DCR_M => write!(f, "DCR M"),
+            MVI_M_D8(x) => write!(f, "MVI M,{:#04X}", x),
+            STC => write!(f, "STC"),
+            DAD_SP => write!(f, "DAD SP"),
+            LDA_ADR(x) => write!(f, "LDA {:#06X}", x),
+            DCX_SP => write!(f, "DCX SP"),
+            INR_A => write!(f, "INR A"),
+            DCR_A => write!(f, "DCR A"),
+            MVI_A_D8(x) => write!(f, "MVI A,{:#04X}", x),
+            CMC => write!(f, "CMC"),
+            MOV_B_B => write!(f, "MOV B,B"),
+            MOV_B_C => write!(f, "MOV B,C"),
+            MOV_B_D => write!(f, "MOV B,D"),
+            MOV_B_E => write!(f, "MOV B,E"),
+            MOV_B_H => write!(f, "MOV B,H"),
+            MOV_B_L => write!(f, "MOV B,L"),
+            MOV_B_M => write!(f, "MOV B,M"),
+            MOV_B_A => write!(f, "MOV B,A"),
+            MOV_C_B => write!(f, "MOV C,B"),
+            MOV_C_C => write!(f, "MOV C,C"),
+            MOV_C_D => write!(f, "MOV C,D"),
+            MOV_C_E => write!(f, "MOV C,E"),
+            MOV_C_H => write!(f, "MOV C,H"),
+            MOV_C_L => write!(f, "MOV C,L"),
+            MOV_C_M => write!(f, "MOV C,M"),
+            MOV_C_A => write!(f, "MOV C,A"),
+            MOV_D_B => write!(f, "MOV D,B"),
+            MOV_D_C => write!(f, "MOV D,C"),
+            MOV_D_D => write!(f, "MOV D,D"),
+            MOV_D_E => write!(f, "MOV D,E"),
+            MOV_D_H => write!(f, "MOV D,H"),
+            MOV_D_L => write!(f, "MOV D,L"),
+            MOV_D_M => write!(f, "MOV D,M"),
+            MOV_D_A => write!(f, "MOV D,A"),
+            MOV_E_B => write!(f, "MOV E,B"),
+            MOV_E_C => write!(f, "MOV E,C"),
+            MOV_E_D => write!(f, "MOV E,D"),
+            MOV_E_E => write!(f, "MOV E,E"),
+            MOV_E_H => write!(f, "MOV E,H"),
+            MOV_E_L => write!(f, "MOV E,L"),
+            MOV_E_M => write!(f, "MOV E,M"),
+            MOV_E_A => write!(f, "MOV E,A"),
+            MOV_H_B => write!(f, "MOV H,B"),
+            MOV_H_C => write!(f, "MOV H,C"),
+            MOV_H_D => write!(f, "MOV H,D"),
+            MOV_H_E => write!(f, "MOV H,E"),
+            MOV_H_H => write!(f, "MOV H,H"),
+            MOV_H_L => write!(f, "MOV H,L"),
+            MOV_H_M => write!(f, "MOV H,M"),
+            MOV_H_A => write!(f, "MOV H,A"),
+            MOV_L_B => write!(f, "MOV L,B"),
+            MOV_L_C => write!(f, "MOV L,C"),
+            MOV_L_D => write!(f, "MOV L,D"),
+            MOV_L_E => write!(f, "MOV L,E"),
+            MOV_L_H => write!(f, "MOV L,H"),
+            MOV_L_L => write!(f, "MOV L,L"),
+            MOV_L_M => write!(f, "MOV L,M"),
+            MOV_L_A => write!(f, "MOV L,A"),
+            MOV_M_B => write!(f, "MOV M,B"),
+            MOV_M_C => write!(f, "MOV M,C"),
+            MOV_M_D => write!(f, "MOV M,D"),
+            MOV_M_E => write!(f, "MOV M,E"),
+            MOV_M_H => write!(f, "MOV M,H"),
+            MOV_M_L => write!(f, "MOV M,L"),
+            HLT => write!(f, "HLT"),
+            MOV_M_A => write!(f, "MOV M,A"),
+            MOV_A_B => write!(f, "MOV A,B"),
+            MOV_A_C => write!(f, "MOV A,C"),
+            MOV_A_D => write!(f, "MOV A,D"),
+            MOV_A_E => write!(f, "MOV A,E"),
+            MOV_A_H => write!(f, "MOV A,H"),
+            MOV_A_L => write!(f, "MOV A,L"),
+            MOV_A_M => write!(f, "MOV A,M"),
+            MOV_A_A => write!(f, "MOV A,A"),
+            ADD_B => write!(f, "ADD B"),
+            ADD_C => write!(f, "ADD C"),
+            ADD_D => write!(f, "ADD D"),
+            ADD_E => write!(f, "ADD E"),
+            ADD_H => write!(f, "ADD H"),
+            ADD_L => write!(f, "ADD L"),
+            ADD_M => write!(f, "ADD M"),
+            ADD_A => write!(f, "ADD A"),
+            ADC_B => write!(f, "ADC B"),
+            ADC_C => write!(f, "ADC C"),
+            ADC_D => write!(f, "ADC D"),
+            ADC_E => write!(f, "ADC E"),
+            ADC_H => write!(f, "ADC H"),
+            ADC_L => write!(f, "ADC L"),
+            ADC_M => write!(f, "ADC M"),
+            ADC_A => write!(f, "ADC A"),
+            SUB_B => write!(f, "SUB B"),
+            SUB_C => write!(f, "SUB C"),
+            SUB_D => write!(f, "SUB D"),
+            SUB_E => write!(f, "SUB E"),
+            SUB_H => write!(f, "SUB H"),
+            SUB_L => write!(f, "SUB L"),
+            SUB_M => write!(f, "SUB M"),
+            SUB_A => write!(f, "SUB A"),
+            SBB_B => write!(f, "SBB B"),
+            SBB_C => write!(f, "SBB C"),
+            SBB_D => write!(f, "SBB D"),
+            SBB_E => write!(f, "SBB E"),
+            SBB_H => write!(f, "SBB H"),
+            SBB_L => write!(f, "SBB L"),
+            SBB_M => write!(f, "SBB M"),
+            SBB_A => write!(f, "SBB A"),
+            ANA_B => write!(f, "ANA B"),
+            ANA_C => write!(f, "ANA C"),
+            ANA_D => write!(f, "ANA D"),
+            ANA_E => write!(f, "ANA E"),
+            ANA_H => write!(f, "ANA H"),
+            ANA_L => write!(f, "ANA L"),
+            ANA_M => write!(f, "ANA M"),
+            ANA_A => write!(f, "ANA A"),
+            XRA_B => write!(f, "XRA B"),
+            XRA_C => write!(f, "XRA C"),
+            XRA_D => write!(f, "XRA D"),
+            XRA_E => write!(f, "XRA E"),
+            XRA_H => write!(f, "XRA H"),
+            XRA_L => write!(f, "XRA L"),
+            XRA_M => write!(f, "XRA M"),
+            XRA_A => write!(f, "XRA A"),
+            ORA_B => write!(f, "ORA B"),
+            ORA_C => write!(f, "ORA C"),
+            ORA_D => write!(f, "ORA D"),
+            ORA_E => write!(f, "ORA E"),
+            ORA_H => write!(f, "ORA H"),
+            ORA_L => write!(f, "ORA L"),
+            ORA_M => write!(f, "ORA M"),
+            ORA_A => write!(f, "ORA A"),
+            CMP_B => write!(f, "CMP B"),
+            CMP_C => write!(f, "CMP C"),
+            CMP_D => write!(f, "CMP D"),
+            CMP_E => write!(f, "CMP E"),
+            CMP_H => write!(f, "CMP H"),
+            CMP_L => write!(f, "CMP L"),
+            CMP_M => write!(f, "CMP M"),
+            CMP_A => write!(f, "CMP A"),
+            RNZ => write!(f, "RNZ"),
+            POP_B => write!(f, "POP B"),
+            JNZ_ADR(x) => write!(f, "JNZ {:#06X}", x),
+            JMP_ADR(x) => write!(f, "JMP {:#06X}", x),
+            CNZ_ADR(x) => write!(f, "CNZ {:#06X}", x),
+            PUSH_B => write!(f, "PUSH B"),
+            ADI_D8(x) => write!(f, "ADI {:#04X}", x),
+            RST_0 => write!(f, "RST 0"),
+            RZ => write!(f, "RZ"),
+            RET => write!(f, "RET"),
+            JZ_ADR(x) => write!(f, "JZ {:#06X}", x),
+            CZ_ADR(x) => write!(f, "CZ {:#06X}", x),
+            CALL_ADR(x) => write!(f, "CALL {:#06X}", x),
+            ACI_D8(x) => write!(f, "ACI {:#04X}", x),
+            RST_1 => write!(f, "RST 1"),
+            RNC => write!(f, "RNC"),
+            POP_D => write!(f, "POP D"),
+            JNC_ADR(x) => write!(f, "JNC {:#06X}", x),
+            OUT_D8(x) => write!(f, "OUT {:#04X}", x),
+            CNC_ADR(x) => write!(f, "CNC {:#06X}", x),
+            PUSH_D => write!(f, "PUSH D"),
+            SUI_D8(x) => write!(f, "SUI {:#04X}", x),
+            RST_2 => write!(f, "RST 2"),
+            RC => write!(f, "RC"),
+            JC_ADR(x) => write!(f, "JC {:#06X}", x),
+            IN_D8(x) => write!(f, "IN {:#04X}", x),
+            CC_ADR(x) => write!(f, "CC {:#06X}", x),
+            SBI_D8(x) => write!(f, "SBI {:#04X}", x),
+            RST_3 => write!(f, "RST 3"),
+            RPO => write!(f, "RPO"),
+            POP_H => write!(f, "POP H"),
+            JPO_ADR(x) => write!(f, "JPO {:#06X}", x),
+            XTHL => write!(f, "XTHL"),
+            CPO_ADR(x) => write!(f, "CPO {:#06X}", x),
+            PUSH_H => write!(f, "PUSH H"),
+            ANI_D8(x) => write!(f, "ANI {:#04X}", x),
+            RST_4 => write!(f, "RST 4"),
+            RPE => write!(f, "RPE"),
+            PCHL => write!(f, "PCHL"),
+            JPE_ADR(x) => write!(f, "JPE {:#06X}", x),
+            XCHG => write!(f, "XCHG"),
+            CPE_ADR(x) => write!(f, "CPE {:#06X}", x),
+            XRI_D8(x) => write!(f, "XRI {:#04X}", x),
+            RST_5 => write!(f, "RST 5"),
+            RP => write!(f, "RP"),
+            POP_PSW => write!(f, "POP PSW"),
+            JP_ADR(x) => write!(f, "JP {:#06X}", x),
+            DI => write!(f, "DI"),
+            CP_ADR(x) => write!(f, "CP {:#06X}", x),
+            PUSH_PSW => write!(f, "PUSH PSW"),
+            ORI_D8(x) => write!(f, "ORI {:#04X}", x),
+            RST_6 => write!(f, "RST 6"),
+            RM => write!(f, "RM"),
+            SPHL => write!(f, "SPHL"),
+            JM_ADR(x) => write!(f, "JM {:#06X}", x),
+            EI => write!(f, "EI"),
+            CM_ADR(x) => write!(f, "CM {:#06X}", x),
+            CPI_D8(x) => write!(f, "CPI {:#04X}", x),
+            RST_7 => write!(f, "RST 7"),
         }
     }
 }
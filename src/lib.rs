@@ -1,2 +1,27 @@
+pub mod breakpoints;
+pub mod callstack;
+pub mod capture;
+pub mod checksum;
+pub mod cpm;
 pub mod cpu;
-pub mod instructions;
\ No newline at end of file
+pub mod crt;
+pub mod debugger;
+pub mod gdb;
+pub mod gif;
+pub mod history;
+pub mod instructions;
+pub mod machine;
+pub mod movie;
+pub mod png;
+pub mod rewind;
+pub mod savestate;
+pub mod screen;
+pub mod session;
+pub mod sound;
+pub mod symbols;
+pub mod terminal;
+pub mod trace;
+pub mod wav;
+#[cfg(feature = "window")]
+pub mod window;
+pub mod y4m;
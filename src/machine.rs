@@ -0,0 +1,551 @@
+use std::{
+    collections::VecDeque,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cpu::Cpu,
+    history::Entry,
+    savestate::{self, Reader, SaveState, Writer},
+};
+
+/// the 8080 in the cabinet runs at 2 MHz
+pub const CLOCK_HZ: u64 = 2_000_000;
+/// 8 KiB of rom and 8 KiB of ram, mirrored through the rest of the address space
+pub const MEMORY_SIZE: usize = 0x4000;
+/// the rom, the first 8 KiB
+pub const ROM_SIZE: usize = 0x2000;
+/// the screen refreshes at 60 Hz and interrupts twice per frame
+pub const CYCLES_PER_HALF_FRAME: u64 = CLOCK_HZ / 120;
+
+/// sleeps between frames to keep a frontend at the machine's real speed
+pub struct Pacer {
+    next: Instant,
+}
+
+impl Pacer {
+    const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    pub fn new() -> Self {
+        Pacer {
+            next: Instant::now(),
+        }
+    }
+
+    /// wait until the next frame is due, however long this one took, but give up
+    /// on catching up after a stall rather than running fast
+    pub fn wait(&mut self) {
+        self.next += Self::FRAME;
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else {
+            self.next = now;
+        }
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// anything that drives a `Cpu` one instruction at a time,
+/// a bare `Cpu` or a whole machine with its own I/O and interrupts
+pub trait Machine {
+    fn cpu(&self) -> &Cpu;
+    fn cpu_mut(&mut self) -> &mut Cpu;
+    /// execute one instruction along with any I/O and interrupts it causes
+    fn step(&mut self);
+    /// undo the last `step` using the cpu history, returns the entries undone,
+    /// newest first, or none once the history is exhausted
+    fn step_back(&mut self) -> Vec<Entry> {
+        self.cpu_mut().step_back().into_iter().collect()
+    }
+    /// the address the stack grows down from, if the machine has a fixed one
+    fn stack_base(&self) -> Option<u16> {
+        None
+    }
+}
+
+impl Machine for Cpu {
+    fn cpu(&self) -> &Cpu {
+        self
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        self
+    }
+
+    fn step(&mut self) {
+        Cpu::step(self);
+    }
+}
+
+/// cabinet controls, read through input ports 1 and 2
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Controls {
+    pub coin: bool,
+    pub p1_start: bool,
+    pub p2_start: bool,
+    pub p1_fire: bool,
+    pub p1_left: bool,
+    pub p1_right: bool,
+    pub p2_fire: bool,
+    pub p2_left: bool,
+    pub p2_right: bool,
+    pub tilt: bool,
+}
+
+/// dip switch settings, read through input port 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dips {
+    /// ships per game, 3 to 6
+    pub ships: u8,
+    /// award the extra ship at 1000 points instead of 1500
+    pub extra_ship_at_1000: bool,
+    /// hide the coin info on the attract screen
+    pub hide_coin_info: bool,
+}
+
+impl Default for Dips {
+    fn default() -> Self {
+        Dips {
+            ships: 3,
+            extra_ship_at_1000: false,
+            hide_coin_info: false,
+        }
+    }
+}
+
+/// the Space Invaders cabinet: rom, ram, the external shift register and the two
+/// video interrupts, RST 1 when the beam reaches the middle of the screen and RST 2
+/// at the start of vblank
+pub struct Invaders {
+    pub cpu: Cpu,
+    pub controls: Controls,
+    pub dips: Dips,
+    shift_register: u16,
+    shift_offset: u8,
+    /// last values written to the sound ports 3 and 5
+    sound: [u8; 2],
+    /// the interrupt fired next, 1 for mid screen or 2 for vblank
+    next_interrupt: u8,
+    next_interrupt_at: u64,
+    frame: u64,
+    /// (cycles, shift register, shift offset, sound) before each OUT, kept while
+    /// the cpu history is enabled so stepping back can undo the writes
+    io_log: VecDeque<(u64, u16, u8, [u8; 2])>,
+}
+
+impl Invaders {
+    pub fn new(rom: &[u8]) -> Self {
+        let mut cpu = Cpu::with_memory_size(MEMORY_SIZE);
+        cpu.load(rom, 0);
+        cpu.set_rom(0..ROM_SIZE);
+        let mut machine = Invaders {
+            cpu,
+            controls: Controls::default(),
+            dips: Dips::default(),
+            shift_register: 0,
+            shift_offset: 0,
+            sound: [0; 2],
+            next_interrupt: 1,
+            next_interrupt_at: CYCLES_PER_HALF_FRAME,
+            frame: 0,
+            io_log: VecDeque::new(),
+        };
+        machine.update_inputs();
+        machine
+    }
+
+    /// number of completed frames
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// the 7 KiB of video ram at 0x2400, one bit per pixel, 32 bytes per column from the bottom
+    pub fn video_ram(&self) -> &[u8] {
+        &self.cpu.memory()[0x2400..0x4000]
+    }
+
+    /// last values written to sound ports 3 and 5
+    pub fn sound(&self) -> [u8; 2] {
+        self.sound
+    }
+
+    /// run until the vblank interrupt of the current frame has fired
+    pub fn run_frame(&mut self) {
+        let frame = self.frame;
+        while self.frame == frame {
+            self.step();
+        }
+    }
+
+    fn log_io(&mut self, cycles: u64) {
+        let oldest = self
+            .cpu
+            .history
+            .iter()
+            .next()
+            .map_or(0, |entry| entry.state.cycles);
+        while self.io_log.front().is_some_and(|&(at, ..)| at < oldest) {
+            self.io_log.pop_front();
+        }
+        self.io_log
+            .push_back((cycles, self.shift_register, self.shift_offset, self.sound));
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            2 => self.shift_offset = value & 0x07,
+            3 => self.sound[0] = value,
+            4 => self.shift_register = (self.shift_register >> 8) | ((value as u16) << 8),
+            5 => self.sound[1] = value,
+            // 6 is the watchdog, there is nothing to reset
+            _ => {}
+        }
+    }
+
+    /// refresh the values the cpu reads with IN
+    fn update_inputs(&mut self) {
+        let (port1, port2) = self.controls.ports(&self.dips);
+        let shifted = self.shift_register << self.shift_offset;
+
+        self.cpu.ports[0] = 0b0000_1110;
+        self.cpu.ports[1] = port1;
+        self.cpu.ports[2] = port2;
+        self.cpu.ports[3] = (shifted >> 8) as u8;
+    }
+}
+
+impl Controls {
+    /// the values of input ports 1 and 2, port 2 also carries the dip switches
+    pub fn ports(&self, dips: &Dips) -> (u8, u8) {
+        let port1 = (self.coin as u8)
+            | (self.p2_start as u8) << 1
+            | (self.p1_start as u8) << 2
+            | 1 << 3
+            | (self.p1_fire as u8) << 4
+            | (self.p1_left as u8) << 5
+            | (self.p1_right as u8) << 6;
+        let ships = dips.ships.clamp(3, 6) - 3;
+        let port2 = ships
+            | (self.tilt as u8) << 2
+            | (dips.extra_ship_at_1000 as u8) << 3
+            | (self.p2_fire as u8) << 4
+            | (self.p2_left as u8) << 5
+            | (self.p2_right as u8) << 6
+            | (dips.hide_coin_info as u8) << 7;
+        (port1, port2)
+    }
+
+    /// the controls pressed in the values of input ports 1 and 2
+    pub fn from_ports(port1: u8, port2: u8) -> Self {
+        let bit = |port: u8, n: u8| port & (1 << n) != 0;
+        Controls {
+            coin: bit(port1, 0),
+            p2_start: bit(port1, 1),
+            p1_start: bit(port1, 2),
+            p1_fire: bit(port1, 4),
+            p1_left: bit(port1, 5),
+            p1_right: bit(port1, 6),
+            tilt: bit(port2, 2),
+            p2_fire: bit(port2, 4),
+            p2_left: bit(port2, 5),
+            p2_right: bit(port2, 6),
+        }
+    }
+
+    pub(crate) fn bits(&self) -> u16 {
+        [
+            self.coin,
+            self.p1_start,
+            self.p2_start,
+            self.p1_fire,
+            self.p1_left,
+            self.p1_right,
+            self.p2_fire,
+            self.p2_left,
+            self.p2_right,
+            self.tilt,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &pressed)| bits | (pressed as u16) << i)
+    }
+
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        let pressed = |i: u16| bits & (1 << i) != 0;
+        Controls {
+            coin: pressed(0),
+            p1_start: pressed(1),
+            p2_start: pressed(2),
+            p1_fire: pressed(3),
+            p1_left: pressed(4),
+            p1_right: pressed(5),
+            p2_fire: pressed(6),
+            p2_left: pressed(7),
+            p2_right: pressed(8),
+            tilt: pressed(9),
+        }
+    }
+}
+
+/// the cpu followed by the controls, dip switches, shift register, sound latches
+/// and interrupt schedule
+impl SaveState for Invaders {
+    const KIND: [u8; 4] = *b"INVD";
+
+    fn save(&self, writer: &mut Writer) {
+        self.cpu.save(writer);
+        writer.u16(self.controls.bits());
+        writer.u8(self.dips.ships);
+        writer.bool(self.dips.extra_ship_at_1000);
+        writer.bool(self.dips.hide_coin_info);
+        writer.u16(self.shift_register);
+        writer.u8(self.shift_offset);
+        writer.bytes(&self.sound);
+        writer.u8(self.next_interrupt);
+        writer.u64(self.next_interrupt_at);
+        writer.u64(self.frame);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), savestate::Error> {
+        let cpu = self.cpu.read_saved(reader)?;
+        let controls = Controls::from_bits(reader.u16()?);
+        let dips = Dips {
+            ships: reader.u8()?,
+            extra_ship_at_1000: reader.bool()?,
+            hide_coin_info: reader.bool()?,
+        };
+        let shift_register = reader.u16()?;
+        let shift_offset = reader.u8()?;
+        let sound = [reader.u8()?, reader.u8()?];
+        let next_interrupt = reader.u8()?;
+        let next_interrupt_at = reader.u64()?;
+        let frame = reader.u64()?;
+        if shift_offset > 7 || !(1..=2).contains(&next_interrupt) {
+            return Err(savestate::Error::Invalid("bad shift offset or interrupt"));
+        }
+
+        self.cpu.apply_saved(cpu);
+        self.controls = controls;
+        self.dips = dips;
+        self.shift_register = shift_register;
+        self.shift_offset = shift_offset;
+        self.sound = sound;
+        self.next_interrupt = next_interrupt;
+        self.next_interrupt_at = next_interrupt_at;
+        self.frame = frame;
+        self.io_log.clear();
+        self.update_inputs();
+        Ok(())
+    }
+}
+
+impl Machine for Invaders {
+    fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    fn step(&mut self) {
+        self.update_inputs();
+        let cycles = self.cpu.cycles();
+        self.cpu.step();
+        if let Some((port, value)) = self.cpu.take_output() {
+            if self.cpu.history.is_enabled() {
+                self.log_io(cycles);
+            }
+            self.output(port, value);
+        }
+        if self.cpu.cycles() >= self.next_interrupt_at {
+            self.cpu.interrupt(self.next_interrupt);
+            if self.next_interrupt == 2 {
+                self.frame += 1;
+            }
+            self.next_interrupt = if self.next_interrupt == 1 { 2 } else { 1 };
+            self.next_interrupt_at += CYCLES_PER_HALF_FRAME;
+        }
+    }
+
+    /// undoes an interrupt together with the instruction before it, the interrupt
+    /// schedule only depends on the cycle count so it is worked out again from there
+    fn step_back(&mut self) -> Vec<Entry> {
+        let mut undone = Vec::new();
+        while let Some(entry) = self.cpu.step_back() {
+            let interrupt = entry.interrupt;
+            undone.push(entry);
+            if !interrupt {
+                break;
+            }
+        }
+        let cycles = self.cpu.cycles();
+        while let Some(&(at, register, offset, sound)) = self.io_log.back() {
+            if at < cycles {
+                break;
+            }
+            self.shift_register = register;
+            self.shift_offset = offset;
+            self.sound = sound;
+            self.io_log.pop_back();
+        }
+        let fired = cycles / CYCLES_PER_HALF_FRAME;
+        self.next_interrupt = if fired.is_multiple_of(2) { 1 } else { 2 };
+        self.next_interrupt_at = (fired + 1) * CYCLES_PER_HALF_FRAME;
+        self.frame = fired / 2;
+        undone
+    }
+
+    /// the rom sets SP to the start of video ram, the stack grows down from there
+    fn stack_base(&self) -> Option<u16> {
+        Some(0x2400)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Controls, Invaders, Machine, CYCLES_PER_HALF_FRAME};
+    use crate::{cpu::Cpu, savestate::SaveState};
+
+    #[test]
+    fn test_shift_register() {
+        // MVI A,0xAB; OUT 4; MVI A,0xCD; OUT 4; MVI A,3; OUT 2; IN 3
+        let program = [
+            0x3E, 0xAB, 0xD3, 0x04, 0x3E, 0xCD, 0xD3, 0x04, 0x3E, 0x03, 0xD3, 0x02, 0xDB, 0x03,
+        ];
+        let mut machine = Invaders::new(&program);
+        for _ in 0..7 {
+            machine.step();
+        }
+        // 0xCDAB << 3 = 0x6D58, high byte after the shift
+        assert_eq!(machine.cpu.a, 0x6D);
+    }
+
+    #[test]
+    fn test_rom_is_read_only() {
+        // MVI A,0x55; STA 0x1FFF; STA 0x2000
+        let program = [0x3E, 0x55, 0x32, 0xFF, 0x1F, 0x32, 0x00, 0x20];
+        let mut machine = Invaders::new(&program);
+        for _ in 0..3 {
+            machine.step();
+        }
+        assert_eq!(machine.cpu.read_byte(0x1FFF), 0x00);
+        assert_eq!(machine.cpu.read_byte(0x2000), 0x55);
+    }
+
+    #[test]
+    fn test_controls() {
+        // IN 1
+        let mut machine = Invaders::new(&[0xDB, 0x01]);
+        machine.controls = Controls {
+            coin: true,
+            p1_left: true,
+            ..Controls::default()
+        };
+        machine.step();
+        assert_eq!(machine.cpu.a, 0b0010_1001);
+    }
+
+    #[test]
+    fn test_interrupts_alternate() {
+        // EI; JMP 0x0001, with RST 1 and RST 2 handlers that EI and RET
+        let mut rom = vec![0; 0x20];
+        rom[0..4].copy_from_slice(&[0xFB, 0xC3, 0x01, 0x00]);
+        rom[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+        let mut machine = Invaders::new(&rom);
+        machine.cpu.sp = 0x2400;
+
+        while machine.cpu.cycles() < CYCLES_PER_HALF_FRAME {
+            machine.step();
+        }
+        assert_eq!(machine.cpu.pc, 0x0008);
+        assert_eq!(machine.frame(), 0);
+
+        machine.run_frame();
+        assert_eq!(machine.cpu.pc, 0x0010);
+        assert_eq!(machine.frame(), 1);
+    }
+
+    #[test]
+    fn test_step_back_across_interrupt_and_output() {
+        // at 0x18: EI; MVI A,0x12; OUT 4; MVI A,0x34; OUT 4; JMP 0x0021,
+        // with EI and RET handlers
+        let mut rom = vec![0; 0x30];
+        rom[0..3].copy_from_slice(&[0xC3, 0x18, 0x00]);
+        rom[0x18..0x24].copy_from_slice(&[
+            0xFB, 0x3E, 0x12, 0xD3, 0x04, 0x3E, 0x34, 0xD3, 0x04, 0xC3, 0x21, 0x00,
+        ]);
+        rom[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+        let mut machine = Invaders::new(&rom);
+        machine.cpu.sp = 0x2400;
+        machine.cpu.history.set_capacity(1_000_000);
+
+        for _ in 0..5 {
+            machine.step();
+        }
+        assert_eq!(machine.shift_register, 0x1200);
+        machine.step();
+        assert_eq!(machine.shift_register, 0x3412);
+        machine.step_back();
+        assert_eq!(machine.shift_register, 0x1200);
+
+        while machine.cpu.cycles() < CYCLES_PER_HALF_FRAME {
+            machine.step();
+        }
+        assert_eq!(machine.cpu.pc, 0x0008);
+        // the interrupt and the jump before it are undone together
+        assert_eq!(machine.step_back().len(), 2);
+        assert_eq!(machine.cpu.pc, 0x0021);
+        machine.step();
+        assert_eq!(machine.cpu.pc, 0x0008);
+
+        while !machine.step_back().is_empty() {}
+        assert_eq!(machine.cpu.cycles(), 0);
+        assert_eq!(machine.shift_register, 0);
+        machine.run_frame();
+        assert_eq!(machine.frame(), 1);
+    }
+
+    #[test]
+    fn test_save_state_resumes_identically() {
+        // EI; loop: OUT 4; INR A; OUT 2; JMP loop, with EI and RET handlers
+        let mut rom = vec![0; 0x30];
+        rom[0..3].copy_from_slice(&[0xC3, 0x18, 0x00]);
+        rom[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x18..0x21].copy_from_slice(&[0xFB, 0xD3, 0x04, 0x3C, 0xD3, 0x02, 0xC3, 0x19, 0x00]);
+        let mut machine = Invaders::new(&rom);
+        machine.cpu.sp = 0x2400;
+        machine.controls.p1_fire = true;
+        machine.dips.ships = 5;
+        machine.run_frame();
+        for _ in 0..1234 {
+            machine.step();
+        }
+        let data = machine.save_state();
+
+        let mut loaded = Invaders::new(&rom);
+        loaded.load_state(&data).unwrap();
+        assert_eq!(loaded.controls, machine.controls);
+        assert_eq!(loaded.dips, machine.dips);
+        assert_eq!(loaded.frame(), 1);
+        for _ in 0..3 {
+            machine.run_frame();
+            loaded.run_frame();
+        }
+        assert_eq!(loaded.cpu.state(), machine.cpu.state());
+        assert_eq!(loaded.save_state(), machine.save_state());
+
+        // a bare cpu state is not an Invaders state
+        assert!(loaded.load_state(&Cpu::new().save_state()).is_err());
+    }
+}
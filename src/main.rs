@@ -1,94 +1,516 @@
-use std::io::Read;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
 
-use invaders::{instructions::Instruction, cpu::Cpu};
+use invaders::{
+    capture::Recorder,
+    checksum::crc32,
+    cpm::Cpm,
+    cpu::Cpu,
+    crt::{Crt, Settings},
+    debugger::Debugger,
+    gdb::GdbStub,
+    instructions,
+    machine::{Dips, Invaders, Machine},
+    movie::Movie,
+    png,
+    savestate::SaveState,
+    screen,
+    symbols::Symbols,
+    terminal::Glyphs,
+    trace::{self, Tracer},
+};
 
 // http://www.emulator101.com/reference/8080-by-opcode.html
 
+const USAGE: &str = "\
+usage: invaders [options]
+  --rom <file>               program to load (default rom/invaders)
+  --bare                     run a bare cpu with 64 KiB of ram instead of the Space Invaders machine
+  --org <addr>               hex load and start address for --bare (default 0)
+  --cpm                      run a CP/M program, such as an 8080 exerciser, printing its output
+  --symbols <file>           names for addresses in the debugger, and with --trace-symbols the trace
+                             (default the rom path with .sym appended, if it exists)
+  --disassemble              print a listing of the whole rom, at --org with --bare
+  --trace <file>             write a one line per instruction trace
+  --trace-symbols            name addresses in the trace, which then no longer diffs with plain ones
+  --diff <reference trace>   run in lockstep with a trace and report the first divergence
+  --steps <n>                stop after n instructions
+  --load <file>              start from a save state
+  --save <file>              write a save state when --steps runs out
+  --debug                    start the interactive debugger
+  --gdb <host:port>          wait for a gdb remote protocol client, e.g. 127.0.0.1:1234
+  --play <movie>             replay a movie headless, printing each frame's CRC-32
+  --input <script>           play a script of held inputs headless instead, see Movie::from_script
+  --frames <n>               run n frames headless, padding or cutting the movie or script
+  --dump <dir>               write frames as PNGs to dir, named frame_<n>.png
+  --dump-frames <list>       which frames to write, e.g. 60,120-130 (default the last)
+  --filter <settings>        CRT filters for the window and --dump, crt for all of them or a
+                             list like overlay,scanlines=0.5,persistence=0.6,bloom=0.4,curvature=0.1
+  --record <file>            record the frames played, headless or not, to a .gif or .y4m
+                             at --scale
+  --audio <file>             record what they sound like to a .wav
+  --record-movie <file>      save the inputs of a window or terminal session as a movie for --play
+  --window                   play in a window, needs the window feature
+  --scale <n>                window pixels per screen pixel, and for filtered --dump (default 2)
+  --aspect                   squeeze the picture to the shape of the cabinet's monitor
+  --fullscreen               start fullscreen
+  --display <w>x<h>          size of the display fullscreen covers (default 1920x1080)
+  --terminal                 play in the terminal, needs the terminal feature
+  --rewind <seconds>         how far back holding backspace rewinds the window or terminal,
+                             0 for not at all (default 10)
+  --glyphs <braille|blocks>  characters the terminal draws with, braille needs 112x66
+                             cells and half blocks 224x130 (default braille)";
+
+struct Options {
+    rom: String,
+    bare: bool,
+    cpm: bool,
+    disassemble: bool,
+    org: u16,
+    symbols: Option<String>,
+    trace: Option<String>,
+    trace_symbols: bool,
+    diff: Option<String>,
+    steps: Option<u64>,
+    load: Option<String>,
+    save: Option<String>,
+    debug: bool,
+    gdb: Option<String>,
+    play: Option<String>,
+    input: Option<String>,
+    frames: Option<usize>,
+    dump: Option<String>,
+    dump_frames: Option<String>,
+    filter: Option<Settings>,
+    record: Option<String>,
+    audio: Option<String>,
+    window: bool,
+    scale: usize,
+    aspect: bool,
+    fullscreen: bool,
+    display: (usize, usize),
+    terminal: bool,
+    glyphs: Glyphs,
+    rewind: usize,
+    record_movie: Option<String>,
+}
+
+impl Options {
+    fn parse() -> Self {
+        let mut options = Options {
+            rom: "rom/invaders".to_string(),
+            bare: false,
+            cpm: false,
+            disassemble: false,
+            org: 0,
+            symbols: None,
+            trace: None,
+            trace_symbols: false,
+            diff: None,
+            steps: None,
+            load: None,
+            save: None,
+            debug: false,
+            gdb: None,
+            play: None,
+            input: None,
+            frames: None,
+            dump: None,
+            dump_frames: None,
+            filter: None,
+            record: None,
+            audio: None,
+            window: false,
+            scale: 2,
+            aspect: false,
+            fullscreen: false,
+            display: (1920, 1080),
+            terminal: false,
+            glyphs: Glyphs::Braille,
+            rewind: 10,
+            record_movie: None,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("{} needs a value\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--rom" => options.rom = value(),
+                "--bare" => options.bare = true,
+                "--cpm" => options.cpm = true,
+                "--disassemble" => options.disassemble = true,
+                "--org" => options.org = u16::from_str_radix(&value(), 16).unwrap(),
+                "--symbols" => options.symbols = Some(value()),
+                "--trace" => options.trace = Some(value()),
+                "--trace-symbols" => options.trace_symbols = true,
+                "--diff" => options.diff = Some(value()),
+                "--steps" => options.steps = Some(value().parse().unwrap()),
+                "--load" => options.load = Some(value()),
+                "--save" => options.save = Some(value()),
+                "--debug" => options.debug = true,
+                "--gdb" => options.gdb = Some(value()),
+                "--play" => options.play = Some(value()),
+                "--input" => options.input = Some(value()),
+                "--frames" => options.frames = Some(value().parse().unwrap()),
+                "--dump" => options.dump = Some(value()),
+                "--dump-frames" => options.dump_frames = Some(value()),
+                "--filter" => {
+                    let filter = value();
+                    let settings = filter
+                        .parse()
+                        .unwrap_or_else(|err| panic!("--filter: {}", err));
+                    options.filter = Some(settings);
+                }
+                "--record" => options.record = Some(value()),
+                "--audio" => options.audio = Some(value()),
+                "--record-movie" => options.record_movie = Some(value()),
+                "--window" => options.window = true,
+                "--scale" => options.scale = value().parse().unwrap(),
+                "--aspect" => options.aspect = true,
+                "--fullscreen" => options.fullscreen = true,
+                "--terminal" => options.terminal = true,
+                "--glyphs" => options.glyphs = value().parse().unwrap(),
+                "--rewind" => options.rewind = value().parse().unwrap(),
+                "--display" => {
+                    let display = value();
+                    let (width, height) = display
+                        .split_once('x')
+                        .unwrap_or_else(|| panic!("bad display size: {}", display));
+                    options.display = (width.parse().unwrap(), height.parse().unwrap());
+                }
+                _ => panic!("unknown argument: {}\n{}", arg, USAGE),
+            }
+        }
+        options
+    }
+}
+
 fn main() {
-    let mut rom = std::fs::File::open("rom/invaders").unwrap();
+    let options = Options::parse();
+
+    let mut rom = File::open(&options.rom).unwrap();
     let mut data: Vec<u8> = Vec::new();
     rom.read_to_end(&mut data).unwrap();
 
-    let mut cpu = Cpu::new();
-    cpu.load(&data, 0);
+    if options.record_movie.is_some() && !options.window && !options.terminal {
+        panic!("--record-movie needs --window or --terminal\n{}", USAGE);
+    }
+
+    if options.play.is_some()
+        || options.input.is_some()
+        || (options.record.is_some() || options.audio.is_some())
+            && !options.window
+            && !options.terminal
+        || options.frames.is_some()
+        || options.dump.is_some()
+    {
+        headless(&data, options);
+        return;
+    }
+
+    if options.window {
+        window(&data, &options);
+        return;
+    }
+
+    if options.terminal {
+        terminal(&data, &options);
+        return;
+    }
+
+    if options.disassemble {
+        let origin = if options.bare { options.org } else { 0 };
+        disassemble(&data, origin, &load_symbols(&options));
+        return;
+    }
+
+    if options.cpm {
+        cpm(&data, options.steps);
+        return;
+    }
 
-    loop {
-        cpu.print_state();
-        cpu.print_stack(0x2400);
-        let (inst, len) = cpu.fetch();
-        // cpu.execute_and_advance(inst, len);
-        // cpu.execute(inst, len);
-        cpu.execute(inst);
-        println!("executed {:X?} ({} bytes)\n\n", inst, len);
+    if options.bare {
+        let mut cpu = Cpu::new();
+        cpu.load(&data, options.org);
+        cpu.pc = options.org;
+        run(cpu, options);
+    } else {
+        run(Invaders::new(&data), options);
     }
 }
 
-/// returns the size of the disassembled instruction
-fn disassemble(data: &[u8], pc: usize) -> usize {
-    print!("{:#06X} - ", pc);
-    let d8 = data[pc + 1];
-    let d16 = ((data[pc + 2] as u16) << 8) | data[pc + 1] as u16;
-    match data[pc] {
-        0x00 => {
-            println!("NOP");
-            1
-        }
-        0x01 => {
-            println!("LXI {:#06X}", d16);
-            3
-        }
-        0x06 => {
-            println!("MVI B,{:#04X}", d8);
-            2
-        }
-        0x21 => {
-            println!("LXI H,{:#06X}", d16);
-            3
-        }
-        0x31 => {
-            println!("LXI SP,{:#06X}", d16);
-            3
-        }
-        0x32 => {
-            println!("STA {:#06X}", d16);
-            3
-        }
-        0x35 => {
-            println!("DCR M");
-            1
-        }
-        0x3E => {
-            println!("MVI A,{:#04X}", d8);
-            2
-        }
-        0x72 => {
-            println!("MOV M,D");
-            1
-        }
-        0xC3 => {
-            println!("JMP {:#06X}", d16);
-            3
+#[cfg(feature = "window")]
+fn window(rom: &[u8], options: &Options) {
+    use invaders::{session::Session, window};
+
+    println!("{}", window::KEYS);
+    let settings = window::Options {
+        scale: options.scale.max(1),
+        aspect: options.aspect,
+        fullscreen: options.fullscreen,
+        display: options.display,
+        filter: options.filter.unwrap_or_default(),
+    };
+    let mut recorder = recorder(options);
+    let mut movie = options
+        .record_movie
+        .as_ref()
+        .map(|_| Movie::new(rom, Dips::default()));
+    let mut session = Session::new(rom, Dips::default(), options.rewind * 60);
+    session.recorder = recorder.as_mut();
+    session.movie = movie.as_mut();
+    window::run(&mut session, settings).unwrap();
+    finish(options, recorder, movie);
+}
+
+#[cfg(not(feature = "window"))]
+fn window(_: &[u8], _: &Options) {
+    eprintln!("built without a window, rebuild with --features window");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "terminal")]
+fn terminal(rom: &[u8], options: &Options) {
+    use invaders::session::Session;
+
+    let mut recorder = recorder(options);
+    let mut movie = options
+        .record_movie
+        .as_ref()
+        .map(|_| Movie::new(rom, Dips::default()));
+    let mut session = Session::new(rom, Dips::default(), options.rewind * 60);
+    session.recorder = recorder.as_mut();
+    session.movie = movie.as_mut();
+    invaders::terminal::run(&mut session, options.glyphs).unwrap();
+    finish(options, recorder, movie);
+}
+
+#[cfg(not(feature = "terminal"))]
+fn terminal(_: &[u8], _: &Options) {
+    eprintln!("built without the terminal frontend, rebuild with --features terminal");
+    std::process::exit(1);
+}
+
+/// a recorder for --record and --audio, if either is given
+fn recorder(options: &Options) -> Option<Recorder> {
+    if options.record.is_none() && options.audio.is_none() {
+        return None;
+    }
+    let video = options.record.as_deref().map(Path::new);
+    let audio = options.audio.as_deref().map(Path::new);
+    Some(Recorder::create(video, audio, options.scale).unwrap_or_else(|err| panic!("{}", err)))
+}
+
+/// finish the recording and write the movie of a window or terminal session
+#[cfg(any(feature = "window", feature = "terminal"))]
+fn finish(options: &Options, recorder: Option<Recorder>, movie: Option<Movie>) {
+    if let Some(recorder) = recorder {
+        recorder.finish().unwrap();
+    }
+    if let (Some(path), Some(movie)) = (&options.record_movie, movie) {
+        movie
+            .save(path)
+            .unwrap_or_else(|err| panic!("{}: {}", path, err));
+        println!("{} frames recorded to {}", movie.len(), path);
+    }
+}
+
+/// the symbols given with --symbols, or the ones next to the rom if there are any
+fn load_symbols(options: &Options) -> Symbols {
+    match &options.symbols {
+        Some(path) => Symbols::load(path).unwrap(),
+        None => Symbols::load(format!("{}.sym", options.rom)).unwrap_or_default(),
+    }
+}
+
+/// print a listing of the whole program
+fn disassemble(program: &[u8], origin: u16, symbols: &Symbols) {
+    for (address, size, instruction) in instructions::disassemble_all(program, origin) {
+        if let Some(name) = symbols.name(address) {
+            println!("{}:", name);
         }
-        0xC5 => {
-            println!("PUSH B");
-            1
+        let offset = address.wrapping_sub(origin) as usize;
+        let bytes: Vec<String> = program[offset..offset + size]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let text = match instruction {
+            Some(instruction) => symbols.instruction(&instruction),
+            None => format!("DB {}", bytes.join(",")),
+        };
+        println!("   {:04X}  {:<8}  {}", address, bytes.join(" "), text);
+    }
+}
+
+/// run a CP/M program until it warm boots, printing its output as it goes
+fn cpm(program: &[u8], steps: Option<u64>) {
+    let mut cpm = Cpm::new(program);
+    let mut printed = 0;
+    let mut stdout = io::stdout();
+    let mut step = 0;
+    while !cpm.is_finished() && steps.is_none_or(|steps| step < steps) {
+        cpm.step();
+        step += 1;
+        if cpm.output().len() > printed {
+            stdout.write_all(&cpm.output()[printed..]).unwrap();
+            stdout.flush().unwrap();
+            printed = cpm.output().len();
         }
-        0xCD => {
-            println!("CALL {:#06X}", d16);
-            3
+        if let Some(error) = cpm.error() {
+            println!();
+            eprintln!("{}", error);
+            std::process::exit(1);
         }
-        0xD5 => {
-            println!("PUSH D");
-            1
+    }
+    println!();
+}
+
+/// replay a movie or input script, or just run, without a window, printing the
+/// CRC-32 of each rendered frame, as the golden tests hash them, and writing the
+/// selected frames as PNGs
+fn headless(rom: &[u8], options: Options) {
+    let mut movie = if let Some(path) = &options.play {
+        Movie::load(path).unwrap_or_else(|err| panic!("{}: {}", path, err))
+    } else if let Some(path) = &options.input {
+        let script = fs::read_to_string(path).unwrap();
+        Movie::from_script(rom, Dips::default(), &script)
+            .unwrap_or_else(|err| panic!("{}: {}", path, err))
+    } else {
+        Movie::new(rom, Dips::default())
+    };
+    if let Some(frames) = options.frames {
+        movie.set_len(frames);
+    }
+    // frames are numbered from 0, like in input scripts
+    let last = (movie.len() as u64).saturating_sub(1);
+    let selected = match &options.dump_frames {
+        Some(list) => parse_frames(list).unwrap_or_else(|err| panic!("--dump-frames: {}", err)),
+        None => vec![(last, last)],
+    };
+    if let Some(dir) = &options.dump {
+        fs::create_dir_all(dir).unwrap();
+    }
+    let mut crt = options.filter.map(Crt::new);
+    let mut recorder = recorder(&options);
+    let scale = options.scale.max(1);
+
+    movie
+        .play(rom, |machine| {
+            let frame = machine.frame() - 1;
+            let pixels = screen::render(machine.video_ram());
+            println!("{} {:08X}", frame, crc32(&pixels));
+            if let Some(recorder) = &mut recorder {
+                recorder.frame(machine).unwrap();
+            }
+            let dir = match &options.dump {
+                Some(dir) => dir,
+                None => return,
+            };
+            // filters run on every frame, persistence carries over from one to the next
+            let filtered = crt
+                .as_mut()
+                .map(|crt| crt.apply(&pixels, screen::WIDTH * scale, screen::HEIGHT * scale));
+            if selected
+                .iter()
+                .any(|&(first, last)| (first..=last).contains(&frame))
+            {
+                let png = match &filtered {
+                    Some(image) => {
+                        png::encode_rgb(image.width as u32, image.height as u32, &image.pixels)
+                    }
+                    None => png::encode_gray(screen::WIDTH as u32, screen::HEIGHT as u32, &pixels),
+                };
+                let path = Path::new(dir).join(format!("frame_{:05}.png", frame));
+                fs::write(path, png).unwrap();
+            }
+        })
+        .unwrap_or_else(|err| panic!("{}", err));
+    if let Some(recorder) = recorder {
+        recorder.finish().unwrap();
+    }
+}
+
+/// a comma separated list of frames and inclusive ranges of frames
+fn parse_frames(list: &str) -> Result<Vec<(u64, u64)>, String> {
+    let parse = |frame: &str| {
+        frame
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("bad frame number: {}", frame))
+    };
+    list.split(',')
+        .map(|range| match range.split_once('-') {
+            Some((first, last)) => Ok((parse(first)?, parse(last)?)),
+            None => Ok((parse(range)?, parse(range)?)),
+        })
+        .collect()
+}
+
+fn run<M: Machine + SaveState>(mut machine: M, options: Options) {
+    if let Some(path) = &options.load {
+        machine
+            .load_file(path)
+            .unwrap_or_else(|err| panic!("{}: {}", path, err));
+    }
+
+    let symbols = load_symbols(&options);
+
+    if options.debug {
+        let mut debugger = Debugger::new(machine);
+        debugger.symbols = symbols;
+        debugger.run(io::stdin().lock(), io::stdout()).unwrap();
+        return;
+    }
+
+    if let Some(address) = options.gdb {
+        println!("waiting for gdb on {}", address);
+        GdbStub::new(machine).listen(address).unwrap();
+        return;
+    }
+
+    if let Some(path) = options.diff {
+        let reference = BufReader::new(File::open(path).unwrap());
+        match trace::diff(&mut machine, reference, 10).unwrap() {
+            Some(divergence) => println!("{}", divergence),
+            None => println!("no divergence from the reference trace"),
         }
-        0xE5 => {
-            println!("PUSH H");
-            1
+        return;
+    }
+
+    let mut tracer = options.trace.map(|path| {
+        let out = BufWriter::new(File::create(path).unwrap());
+        if options.trace_symbols {
+            Tracer::with_symbols(out, symbols)
+        } else {
+            Tracer::new(out)
         }
-        0xF5 => {
-            println!("PUSH PSW");
-            1
+    });
+
+    let mut step = 0;
+    while options.steps.is_none_or(|steps| step < steps) {
+        if let Some(tracer) = &mut tracer {
+            tracer.trace(machine.cpu()).unwrap();
+        } else {
+            machine.cpu().print_state();
+            if let Some(base) = machine.stack_base() {
+                machine.cpu().print_stack(base);
+            }
+            let (inst, len) = machine.cpu().fetch();
+            println!("executing {} ({} bytes)\n\n", inst, len);
         }
-        _ => panic!("unimplemented instruction: {:#04X}", data[pc]),
+        machine.step();
+        step += 1;
+    }
+
+    if let Some(tracer) = &mut tracer {
+        tracer.flush().unwrap();
+    }
+    if let Some(path) = options.save {
+        machine.save_file(path).unwrap();
     }
 }
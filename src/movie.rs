@@ -0,0 +1,369 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    checksum::crc32,
+    machine::{Controls, Dips, Invaders},
+};
+
+/// the first bytes of every movie
+pub const MAGIC: [u8; 8] = *b"8080MOVI";
+/// bumped whenever the layout changes
+pub const VERSION: u16 = 1;
+
+/// the most frames a movie from a script may have, a day of play
+pub const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// magic, version, rom hash, dips and frame count
+const HEADER_SIZE: usize = 8 + 2 + 4 + 3 + 4;
+
+/// the inputs of a session, one pair of port 1 and 2 values per frame, enough to
+/// replay it from power on and get the same frames again
+///
+/// a movie file is laid out as:
+///
+/// - the 8 byte magic `8080MOVI`
+/// - the format version, u16
+/// - the CRC-32 of the rom, u32
+/// - the dip switches: ships, extra ship at 1000 and hide coin info, one byte each
+/// - the number of frames, u32, and the port 1 and port 2 values of each
+///
+/// all numbers are little endian
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u32,
+    pub dips: Dips,
+    /// the values of input ports 1 and 2 during each frame
+    pub inputs: Vec<[u8; 2]>,
+}
+
+impl Movie {
+    /// an empty movie for `rom`, the machine it is recorded on must start from
+    /// `Movie::start`
+    pub fn new(rom: &[u8], dips: Dips) -> Self {
+        Movie {
+            rom_hash: crc32(rom),
+            dips,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// number of frames recorded
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// a freshly powered on machine with the movie's dip switches, checking that
+    /// `rom` is the one it was recorded with
+    pub fn start(&self, rom: &[u8]) -> Result<Invaders, Error> {
+        let actual = crc32(rom);
+        if actual != self.rom_hash {
+            return Err(Error::RomMismatch {
+                expected: self.rom_hash,
+                actual,
+            });
+        }
+        let mut machine = Invaders::new(rom);
+        machine.dips = self.dips;
+        Ok(machine)
+    }
+
+    /// run one frame of `machine` with its current controls and record them
+    pub fn record(&mut self, machine: &mut Invaders) {
+        let (port1, port2) = machine.controls.ports(&self.dips);
+        self.inputs.push([port1, port2]);
+        machine.run_frame();
+    }
+
+    /// replay every frame from power on, calling `on_frame` after each,
+    /// returns the machine as it is after the last frame
+    pub fn play<F: FnMut(&Invaders)>(
+        &self,
+        rom: &[u8],
+        mut on_frame: F,
+    ) -> Result<Invaders, Error> {
+        let mut machine = self.start(rom)?;
+        for &[port1, port2] in &self.inputs {
+            machine.controls = Controls::from_ports(port1, port2);
+            machine.run_frame();
+            on_frame(&machine);
+        }
+        Ok(machine)
+    }
+
+    /// a movie from an input script, one line per held input:
+    ///
+    /// ```text
+    /// # frames are counted from 0, ranges include the last frame
+    /// 100-104 coin
+    /// 160 p1_start
+    /// 300-320 p1_left p1_fire
+    /// ```
+    ///
+    /// the movie ends with the last frame the script mentions, which must be
+    /// under `MAX_FRAMES`
+    pub fn from_script(rom: &[u8], dips: Dips, script: &str) -> Result<Self, String> {
+        let mut controls: Vec<Controls> = Vec::new();
+        for line in script.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut words = line.split_whitespace();
+            let frames = match words.next() {
+                Some(frames) => frames,
+                None => continue,
+            };
+            let parse = |frame: &str| {
+                frame
+                    .parse::<usize>()
+                    .map_err(|_| format!("bad frame number: {}", frame))
+            };
+            let (first, last) = match frames.split_once('-') {
+                Some((first, last)) => (parse(first)?, parse(last)?),
+                None => (parse(frames)?, parse(frames)?),
+            };
+            if last < first {
+                return Err(format!("frame range ends before it starts: {}", frames));
+            }
+            if last >= MAX_FRAMES {
+                return Err(format!(
+                    "frame {} is past the last a movie holds, {}",
+                    last,
+                    MAX_FRAMES - 1
+                ));
+            }
+            if controls.len() <= last {
+                controls.resize(last + 1, Controls::default());
+            }
+            let held = words.collect::<Vec<_>>();
+            if held.is_empty() {
+                return Err(format!("no inputs for frames {}", frames));
+            }
+            for name in held {
+                for frame in &mut controls[first..=last] {
+                    press(frame, name)?;
+                }
+            }
+        }
+        let mut movie = Movie::new(rom, dips);
+        movie.inputs = controls
+            .iter()
+            .map(|controls| {
+                let (port1, port2) = controls.ports(&dips);
+                [port1, port2]
+            })
+            .collect();
+        Ok(movie)
+    }
+
+    /// pad with frames of no input, or cut, to exactly `frames` frames
+    pub fn set_len(&mut self, frames: usize) {
+        let (port1, port2) = Controls::default().ports(&self.dips);
+        self.inputs.resize(frames, [port1, port2]);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + 2 * self.inputs.len());
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.push(self.dips.ships);
+        data.push(self.dips.extra_ship_at_1000 as u8);
+        data.push(self.dips.hide_coin_info as u8);
+        data.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            data.extend_from_slice(input);
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        if data[0..8] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = u16::from_le_bytes([data[8], data[9]]);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let rom_hash = u32::from_le_bytes([data[10], data[11], data[12], data[13]]);
+        let dips = Dips {
+            ships: data[14],
+            extra_ship_at_1000: data[15] != 0,
+            hide_coin_info: data[16] != 0,
+        };
+        let frames = u32::from_le_bytes([data[17], data[18], data[19], data[20]]) as usize;
+        let body = &data[HEADER_SIZE..];
+        if body.len() < 2 * frames {
+            return Err(Error::Truncated);
+        }
+        let inputs = body[..2 * frames]
+            .chunks_exact(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect();
+        Ok(Movie {
+            rom_hash,
+            dips,
+            inputs,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
+
+fn press(controls: &mut Controls, name: &str) -> Result<(), String> {
+    let input = match name {
+        "coin" => &mut controls.coin,
+        "p1_start" => &mut controls.p1_start,
+        "p2_start" => &mut controls.p2_start,
+        "p1_fire" => &mut controls.p1_fire,
+        "p1_left" => &mut controls.p1_left,
+        "p1_right" => &mut controls.p1_right,
+        "p2_fire" => &mut controls.p2_fire,
+        "p2_left" => &mut controls.p2_left,
+        "p2_right" => &mut controls.p2_right,
+        "tilt" => &mut controls.tilt,
+        _ => return Err(format!("unknown input: {}", name)),
+    };
+    *input = true;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// the data ends before the header or the inputs do
+    Truncated,
+    /// the movie was recorded with a different rom
+    RomMismatch {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::BadMagic => write!(f, "not a movie"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "movie version {} is not supported, expected {}",
+                version, VERSION
+            ),
+            Error::Truncated => write!(f, "movie is truncated"),
+            Error::RomMismatch { expected, actual } => write!(
+                f,
+                "movie was recorded with a rom with CRC-32 {:08X}, this one is {:08X}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Movie};
+    use crate::{
+        checksum::crc32,
+        machine::{Controls, Dips},
+    };
+
+    fn rom() -> Vec<u8> {
+        std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders")).unwrap()
+    }
+
+    #[test]
+    fn test_replay_is_identical() {
+        let rom = rom();
+        let dips = Dips {
+            ships: 5,
+            ..Dips::default()
+        };
+        let mut movie = Movie::new(&rom, dips);
+        let mut machine = movie.start(&rom).unwrap();
+        let mut frames = Vec::new();
+        for frame in 0..400 {
+            // insert a coin, start a one player game, then move and fire
+            machine.controls = Controls {
+                coin: (100..105).contains(&frame),
+                p1_start: (160..165).contains(&frame),
+                p1_left: (250..300).contains(&frame),
+                p1_fire: frame >= 300 && frame % 20 < 3,
+                ..Controls::default()
+            };
+            movie.record(&mut machine);
+            frames.push(crc32(machine.video_ram()));
+        }
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.len(), 400);
+        assert_eq!(movie.dips.ships, 5);
+        let mut replayed = Vec::new();
+        let end = movie
+            .play(&rom, |machine| replayed.push(crc32(machine.video_ram())))
+            .unwrap();
+        assert_eq!(replayed, frames);
+        assert_eq!(end.cpu.cycles(), machine.cpu.cycles());
+        // the game actually started, so the inputs reached it
+        assert!(frames[399] != frames[150]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let rom = rom();
+        let movie = Movie::new(&rom, Dips::default());
+        assert!(matches!(
+            movie.play(&rom[1..], |_| {}),
+            Err(Error::RomMismatch { .. })
+        ));
+
+        let mut data = movie.to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(&data[..10]),
+            Err(Error::Truncated)
+        ));
+        data[0] = b'X';
+        assert!(matches!(Movie::from_bytes(&data), Err(Error::BadMagic)));
+    }
+
+    #[test]
+    fn test_script() {
+        let script = "# a game\n2-3 coin\n 4 p1_start p1_fire # both\n\n";
+        let mut movie = Movie::from_script(&[], Dips::default(), script).unwrap();
+        let inputs: Vec<Controls> = movie
+            .inputs
+            .iter()
+            .map(|&[port1, port2]| Controls::from_ports(port1, port2))
+            .collect();
+        assert_eq!(inputs.len(), 5);
+        assert_eq!(inputs[0], Controls::default());
+        assert!(inputs[2].coin && inputs[3].coin && !inputs[4].coin);
+        assert!(inputs[4].p1_start && inputs[4].p1_fire);
+        movie.set_len(8);
+        assert_eq!(movie.inputs[7], movie.inputs[0]);
+
+        for bad in ["x coin", "5-3 coin", "1 jump", "1", "0-99999999999 coin"] {
+            assert!(Movie::from_script(&[], Dips::default(), bad).is_err());
+        }
+    }
+}
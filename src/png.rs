@@ -0,0 +1,143 @@
+use crate::checksum::{adler32, crc32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// the most a stored deflate block can hold
+const MAX_STORED: usize = 0xFFFF;
+
+/// an 8 bit grayscale png, `pixels` one byte per pixel, row by row from the top
+pub fn encode_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    encode(width, height, 0, 1, pixels)
+}
+
+/// an 8 bit rgb png, `pixels` three bytes per pixel, row by row from the top
+pub fn encode_rgb(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    encode(width, height, 2, 3, pixels)
+}
+
+/// the image data is stored rather than compressed, which keeps this small and is
+/// fine for the few screenshots a test run writes
+fn encode(width: u32, height: u32, color_type: u8, channels: usize, pixels: &[u8]) -> Vec<u8> {
+    let stride = width as usize * channels;
+    assert_eq!(pixels.len(), stride * height as usize, "wrong image size");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // bit depth, color type, compression, filter and interlace method
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    // every row starts with filter type 0, none
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(stride.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// a zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED).max(1);
+    let mut out = Vec::with_capacity(data.len() + 5 * blocks + 6);
+    // deflate with a 32 KiB window, no preset dictionary, fastest
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// length, type, data and the CRC-32 of type and data
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_gray, encode_rgb, SIGNATURE};
+    use crate::checksum::{adler32, crc32};
+
+    /// the chunks of a png, checking their CRCs
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&rest[4..8 + len]));
+            chunks.push((kind, &rest[8..8 + len]));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// the data in a zlib stream of stored blocks
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let mut out = Vec::new();
+        let mut rest = &zlib[2..];
+        loop {
+            let last = rest[0] & 1 == 1;
+            assert_eq!(rest[0] >> 1, 0, "not a stored block");
+            let len = u16::from_le_bytes([rest[1], rest[2]]);
+            assert_eq!(!len, u16::from_le_bytes([rest[3], rest[4]]));
+            out.extend_from_slice(&rest[5..5 + len as usize]);
+            rest = &rest[5 + len as usize..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(rest, adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn test_gray() {
+        let pixels: Vec<u8> = (0..6).map(|i| i * 40).collect();
+        let png = encode_gray(3, 2, &pixels);
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+        assert_eq!(
+            inflate_stored(chunks[1].1),
+            [0, 0, 40, 80, 0, 120, 160, 200]
+        );
+    }
+
+    #[test]
+    fn test_rgb_spans_several_blocks() {
+        let pixels: Vec<u8> = (0..300 * 100 * 3).map(|i| i as u8).collect();
+        let png = encode_rgb(300, 100, &pixels);
+        let chunks = chunks(&png);
+        assert_eq!(chunks[0].1[9], 2);
+        let raw = inflate_stored(chunks[1].1);
+        assert_eq!(raw.len(), 100 * (1 + 900));
+        for (row, pixels) in raw.chunks(901).zip(pixels.chunks(900)) {
+            assert_eq!(row[0], 0);
+            assert_eq!(&row[1..], pixels);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+
+use crate::savestate::SaveState;
+
+/// a ring of recent save states for rewinding live play
+///
+/// the newest state is kept whole, each older one as the xor against the state after
+/// it, run length encoded, so consecutive frames that only touch a few hundred bytes
+/// of ram cost about that much and dropping the oldest state needs no re-encoding
+pub struct Rewind {
+    /// the most recent state, whole
+    latest: Option<Vec<u8>>,
+    /// deltas to the states before `latest`, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    max_states: usize,
+    max_bytes: usize,
+    used: usize,
+}
+
+impl Rewind {
+    /// keep at most `max_states` states in about `max_bytes` of memory, when recording
+    /// once per frame `max_states` is the number of frames that can be rewound
+    pub fn new(max_states: usize, max_bytes: usize) -> Self {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            max_states: max_states.max(1),
+            max_bytes,
+            used: 0,
+        }
+    }
+
+    /// number of states that can be rewound to
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// bytes used by the stored states
+    pub fn memory_usage(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    /// add the current state of `machine`, typically once per frame
+    pub fn record<S: SaveState>(&mut self, machine: &S) {
+        let state = machine.save_state();
+        match self.latest.take() {
+            Some(previous) if previous.len() == state.len() => {
+                let delta = encode(&previous, &state);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+                self.used -= previous.len();
+            }
+            Some(_) => self.clear(),
+            None => {}
+        }
+        self.used += state.len();
+        self.latest = Some(state);
+        while self.len() > self.max_states
+            || (self.used > self.max_bytes && !self.deltas.is_empty())
+        {
+            if let Some(delta) = self.deltas.pop_front() {
+                self.used -= delta.len();
+            }
+        }
+    }
+
+    /// restore the most recent state and forget it, so each call goes one state further
+    /// back, returns false once there is nothing left to rewind to
+    pub fn rewind<S: SaveState>(&mut self, machine: &mut S) -> bool {
+        let mut latest = match self.latest.take() {
+            Some(latest) => latest,
+            None => return false,
+        };
+        // the states were made by save_state, a failure here is a bug
+        machine
+            .load_state(&latest)
+            .expect("rewind state does not load");
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.used -= delta.len();
+                apply(&delta, &mut latest);
+                self.latest = Some(latest);
+            }
+            None => self.used = 0,
+        }
+        true
+    }
+}
+
+/// `from ^ to` as runs of (zeros, literal count, literal bytes), the counts as
+/// little endian base 128 varints
+fn encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < from.len() {
+        let zeros = from[i..]
+            .iter()
+            .zip(&to[i..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        i += zeros;
+        let literals = from[i..]
+            .iter()
+            .zip(&to[i..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend((i..i + literals).map(|j| from[j] ^ to[j]));
+        i += literals;
+    }
+    out
+}
+
+/// xor an encoded delta into `data`
+fn apply(delta: &[u8], data: &mut [u8]) {
+    let mut i = 0;
+    let mut input = delta;
+    while !input.is_empty() {
+        i += read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        for (byte, &x) in data[i..i + literals].iter_mut().zip(&input[..literals]) {
+            *byte ^= x;
+        }
+        input = &input[literals..];
+        i += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, encode, Rewind};
+    use crate::{
+        machine::{Invaders, Machine},
+        savestate::SaveState,
+    };
+
+    fn machine() -> Invaders {
+        // EI; loop: INR M; INR L; JMP loop, with EI and RET handlers
+        let mut rom = vec![0; 0x30];
+        rom[0..3].copy_from_slice(&[0xC3, 0x18, 0x00]);
+        rom[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]);
+        rom[0x18..0x1E].copy_from_slice(&[0xFB, 0x34, 0x2C, 0xC3, 0x19, 0x00]);
+        let mut machine = Invaders::new(&rom);
+        machine.cpu.sp = 0x2400;
+        machine.cpu.set_hl(0x2400);
+        machine
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let from: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut to = from.clone();
+        to[0] = 0xFF;
+        to[200..210].fill(0);
+        let delta = encode(&from, &to);
+        assert!(delta.len() < 30);
+        let mut data = to.clone();
+        apply(&delta, &mut data);
+        assert_eq!(data, from);
+    }
+
+    #[test]
+    fn test_rewind_frame_by_frame() {
+        let mut machine = machine();
+        let mut rewind = Rewind::new(10, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..15 {
+            machine.run_frame();
+            rewind.record(&machine);
+            states.push(machine.save_state());
+        }
+        assert_eq!(rewind.len(), 10);
+        // far smaller than ten whole states
+        assert!(rewind.memory_usage() < 2 * states[0].len());
+
+        for expected in states.iter().rev().take(10) {
+            assert!(rewind.rewind(&mut machine));
+            assert_eq!(&machine.save_state(), expected);
+        }
+        assert!(!rewind.rewind(&mut machine));
+        assert_eq!(rewind.memory_usage(), 0);
+
+        // play resumes from the oldest state that was kept
+        machine.run_frame();
+        assert_eq!(machine.frame(), 7);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut machine = machine();
+        let size = machine.save_state().len();
+        let mut rewind = Rewind::new(1000, size + 3000);
+        for _ in 0..100 {
+            machine.run_frame();
+            rewind.record(&machine);
+            assert!(rewind.memory_usage() <= size + 3000);
+        }
+        assert!(rewind.len() > 1 && rewind.len() < 100);
+        machine.step();
+        assert!(rewind.rewind(&mut machine));
+        assert_eq!(machine.frame(), 100);
+    }
+}
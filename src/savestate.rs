@@ -0,0 +1,236 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::checksum::crc32;
+
+/// the first bytes of every save state
+pub const MAGIC: [u8; 8] = *b"8080SAVE";
+/// bumped whenever the layout of any payload changes
+pub const VERSION: u16 = 1;
+
+/// magic, version, kind and payload length
+const HEADER_SIZE: usize = 8 + 2 + 4 + 4;
+
+/// something that can be saved to and restored from a save state
+///
+/// a save state is laid out as:
+///
+/// - the 8 byte magic `8080SAVE`
+/// - the format version, u16
+/// - the 4 byte `KIND` of what was saved, so a state is not loaded into the wrong machine
+/// - the payload length, u32, and the payload
+/// - a CRC-32 of everything before it, u32
+///
+/// all numbers are little endian
+pub trait SaveState {
+    const KIND: [u8; 4];
+
+    fn save(&self, writer: &mut Writer);
+    /// read the whole payload before changing anything, so a bad one leaves `self` alone
+    fn load(&mut self, reader: &mut Reader) -> Result<(), Error>;
+
+    /// the complete save state, header and checksum included
+    fn save_state(&self) -> Vec<u8> {
+        let mut payload = Writer::new();
+        self.save(&mut payload);
+        let mut writer = Writer::new();
+        writer.bytes(&MAGIC);
+        writer.u16(VERSION);
+        writer.bytes(&Self::KIND);
+        writer.u32(payload.data.len() as u32);
+        writer.bytes(&payload.data);
+        let checksum = crc32(&writer.data);
+        writer.u32(checksum);
+        writer.data
+    }
+
+    /// validate a save state and restore it, nothing is changed if it is invalid
+    fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let payload = validate(data, Self::KIND)?;
+        self.load(&mut Reader::new(payload))
+    }
+
+    fn save_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.load_state(&fs::read(path)?)
+    }
+}
+
+/// check the header and checksum, returns the payload
+fn validate(data: &[u8], kind: [u8; 4]) -> Result<&[u8], Error> {
+    if data.len() < HEADER_SIZE + 4 {
+        return Err(Error::Truncated);
+    }
+    if data[0..8] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    let mut header = Reader::new(&data[8..HEADER_SIZE]);
+    let version = header.u16()?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let found = [header.u8()?, header.u8()?, header.u8()?, header.u8()?];
+    if found != kind {
+        return Err(Error::WrongKind {
+            expected: kind,
+            found,
+        });
+    }
+    let len = header.u32()? as usize;
+    let end = HEADER_SIZE + len;
+    if data.len() < end + 4 {
+        return Err(Error::Truncated);
+    }
+    let expected = Reader::new(&data[end..end + 4]).u32()?;
+    let actual = crc32(&data[..end]);
+    if expected != actual {
+        return Err(Error::Checksum { expected, actual });
+    }
+    Ok(&data[HEADER_SIZE..end])
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// a state saved from a different kind of machine
+    WrongKind {
+        expected: [u8; 4],
+        found: [u8; 4],
+    },
+    Checksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// the data ends before the header or payload does
+    Truncated,
+    /// the payload does not make sense for this machine
+    Invalid(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::BadMagic => write!(f, "not a save state"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported, expected {}",
+                version, VERSION
+            ),
+            Error::WrongKind { expected, found } => write!(
+                f,
+                "save state is for {}, expected {}",
+                String::from_utf8_lossy(found).trim(),
+                String::from_utf8_lossy(expected).trim()
+            ),
+            Error::Checksum { expected, actual } => write!(
+                f,
+                "save state checksum is {:08X}, expected {:08X}",
+                actual, expected
+            ),
+            Error::Truncated => write!(f, "save state is truncated"),
+            Error::Invalid(reason) => write!(f, "invalid save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// builds a payload, little endian
+#[derive(Debug, Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// reads a payload, little endian
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid("bad boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,115 @@
+/// the monitor is mounted rotated, so the picture is 224 pixels wide and 256 high
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+/// the width the picture has on the cabinet's 3:4 monitor, its pixels are narrower
+/// than they are tall
+pub const CORRECTED_WIDTH: usize = HEIGHT * 3 / 4;
+
+/// turn video ram into an upright picture, one byte per pixel, 0 or 0xFF, row by row
+/// from the top
+///
+/// video ram holds 224 columns of 32 bytes, each column running from the bottom of
+/// the screen up with the lowest bit of each byte lowest
+pub fn render(video_ram: &[u8]) -> Vec<u8> {
+    let mut pixels = vec![0; WIDTH * HEIGHT];
+    for (x, column) in video_ram.chunks(HEIGHT / 8).take(WIDTH).enumerate() {
+        for (i, &byte) in column.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (1 << bit) != 0 {
+                    let y = HEIGHT - 1 - (i * 8 + bit);
+                    pixels[y * WIDTH + x] = 0xFF;
+                }
+            }
+        }
+    }
+    pixels
+}
+
+pub const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+pub const RED: [u8; 3] = [0xFF, 0x30, 0x30];
+pub const GREEN: [u8; 3] = [0x30, 0xFF, 0x30];
+
+/// the colour of the cellophane strips stuck over the monitor at a point of the
+/// upright picture: red where the saucer flies, green over the shields and the
+/// cannon, and over the reserve ships but not the credit count below them
+pub fn overlay(x: usize, y: usize) -> [u8; 3] {
+    match y {
+        32..=63 => RED,
+        184..=239 => GREEN,
+        240.. if (16..134).contains(&x) => GREEN,
+        _ => WHITE,
+    }
+}
+
+/// the largest whole number scale of the picture that fits in `width` by `height`,
+/// at least 1, and the size it comes to, squeezed to the monitor's shape if `aspect`
+pub fn fit(width: usize, height: usize, aspect: bool) -> (usize, usize, usize) {
+    let picture_width = if aspect { CORRECTED_WIDTH } else { WIDTH };
+    let scale = (width / picture_width).min(height / HEIGHT).max(1);
+    (scale, picture_width * scale, HEIGHT * scale)
+}
+
+/// stretch a picture from `render` to `width` by `height`, each pixel taken from
+/// the source pixel under its centre
+pub fn scale(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let columns: Vec<usize> = (0..width)
+        .map(|x| (2 * x + 1) * WIDTH / (2 * width))
+        .collect();
+    let mut scaled = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &pixels[(2 * y + 1) * HEIGHT / (2 * height) * WIDTH..][..WIDTH];
+        scaled.extend(columns.iter().map(|&x| row[x]));
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit, overlay, render, scale, GREEN, HEIGHT, RED, WHITE, WIDTH};
+
+    #[test]
+    fn test_render_is_rotated() {
+        let mut video_ram = vec![0; 0x1C00];
+        // bottom left corner
+        video_ram[0] = 0x01;
+        // top of the second column
+        video_ram[63] = 0x80;
+        // bottom right corner
+        video_ram[0x1C00 - 32] = 0x01;
+        let pixels = render(&video_ram);
+        let lit: Vec<(usize, usize)> = (0..WIDTH * HEIGHT)
+            .filter(|&i| pixels[i] == 0xFF)
+            .map(|i| (i % WIDTH, i / WIDTH))
+            .collect();
+        assert_eq!(lit, [(1, 0), (0, 255), (223, 255)]);
+    }
+
+    #[test]
+    fn test_fit_scale_and_overlay() {
+        // whole scales only, limited by the tighter side
+        assert_eq!(fit(1920, 1080, false), (4, 896, 1024));
+        assert_eq!(fit(1920, 1080, true), (4, 768, 1024));
+        assert_eq!(fit(600, 2000, false), (2, 448, 512));
+        assert_eq!(fit(100, 100, false), (1, 224, 256));
+
+        assert_eq!(overlay(100, 10), WHITE);
+        assert_eq!(overlay(100, 40), RED);
+        assert_eq!(overlay(0, 200), GREEN);
+        assert_eq!(overlay(20, 250), GREEN);
+        assert_eq!(overlay(200, 250), WHITE);
+
+        let mut pixels = vec![0; WIDTH * HEIGHT];
+        pixels[0] = 0xFF;
+        pixels[WIDTH * HEIGHT - 1] = 0x80;
+        let scaled = scale(&pixels, WIDTH * 2, HEIGHT * 2);
+        assert_eq!(scaled.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(&scaled[..3], [0xFF, 0xFF, 0]);
+        assert_eq!(scaled[WIDTH * 2], 0xFF);
+        assert_eq!(scaled[scaled.len() - 1], 0x80);
+        // squeezed, every output pixel still comes from the source
+        let squeezed = scale(&pixels, 192, HEIGHT);
+        assert_eq!(squeezed.len(), 192 * HEIGHT);
+        assert_eq!(squeezed[0], 0xFF);
+        assert_eq!(squeezed[192 * HEIGHT - 1], 0x80);
+    }
+}
@@ -0,0 +1,164 @@
+use std::io;
+
+use crate::{
+    capture::Recorder,
+    machine::{Controls, Dips, Invaders},
+    movie::Movie,
+    rewind::Rewind,
+};
+
+/// memory the rewind buffer may use, a frame usually costs well under 1 KiB
+const REWIND_BYTES: usize = 64 << 20;
+
+/// a game played live, shared by the window and the terminal: the machine, the
+/// frames kept to rewind to, and the recorder and movie the frames played go to
+pub struct Session<'a> {
+    pub machine: Invaders,
+    rom: &'a [u8],
+    dips: Dips,
+    rewind: Option<Rewind>,
+    /// gets each frame played, frames rewound over stay in the recording
+    pub recorder: Option<&'a mut Recorder>,
+    /// gets the inputs of each frame played, it must be empty and for the same rom
+    /// and dips, it follows rewinds and starts over on a reset
+    pub movie: Option<&'a mut Movie>,
+}
+
+impl<'a> Session<'a> {
+    /// a freshly powered on machine that can be rewound by up to `rewind` frames,
+    /// none turns rewinding off
+    pub fn new(rom: &'a [u8], dips: Dips, rewind: usize) -> Self {
+        let mut machine = Invaders::new(rom);
+        machine.dips = dips;
+        Session {
+            machine,
+            rom,
+            dips,
+            rewind: (rewind > 0).then(|| Rewind::new(rewind, REWIND_BYTES)),
+            recorder: None,
+            movie: None,
+        }
+    }
+
+    /// power the machine off and on again, forgetting the frames before
+    pub fn reset(&mut self) {
+        self.machine = Invaders::new(self.rom);
+        self.machine.dips = self.dips;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        if let Some(movie) = &mut self.movie {
+            movie.inputs.clear();
+        }
+    }
+
+    /// run a frame with `controls` held
+    pub fn frame(&mut self, controls: Controls) -> io::Result<()> {
+        // the state from before the frame, so a rewind goes back to the picture
+        // before this one
+        if let Some(rewind) = &mut self.rewind {
+            rewind.record(&self.machine);
+        }
+        self.machine.controls = controls;
+        match &mut self.movie {
+            Some(movie) => movie.record(&mut self.machine),
+            None => self.machine.run_frame(),
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.frame(&self.machine)?;
+        }
+        Ok(())
+    }
+
+    /// go back a frame, returns false when there is nothing left to go back to
+    pub fn rewind(&mut self) -> bool {
+        let rewound = match &mut self.rewind {
+            Some(rewind) => rewind.rewind(&mut self.machine),
+            None => false,
+        };
+        if let Some(movie) = &mut self.movie {
+            movie.inputs.truncate(self.machine.frame() as usize);
+        }
+        rewound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Session;
+    use crate::{
+        machine::{Controls, Dips},
+        movie::Movie,
+        savestate::SaveState,
+    };
+
+    #[test]
+    fn test_rewind_and_reset() {
+        let rom = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders")).unwrap();
+        let mut session = Session::new(&rom, Dips::default(), 3);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            states.push(session.machine.save_state());
+            session.frame(Controls::default()).unwrap();
+        }
+        for expected in states.iter().rev().take(3) {
+            assert!(session.rewind());
+            assert_eq!(&session.machine.save_state(), expected);
+        }
+        assert!(!session.rewind());
+        assert_eq!(session.machine.frame(), 2);
+
+        session.frame(Controls::default()).unwrap();
+        session.reset();
+        assert_eq!(session.machine.frame(), 0);
+        assert!(!session.rewind());
+
+        let mut session = Session::new(&rom, Dips::default(), 0);
+        session.frame(Controls::default()).unwrap();
+        assert!(!session.rewind());
+    }
+
+    #[test]
+    fn test_movie_follows_rewinds_and_resets() {
+        let rom = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders")).unwrap();
+        let mut movie = Movie::new(&rom, Dips::default());
+        let mut session = Session::new(&rom, Dips::default(), 60);
+        session.movie = Some(&mut movie);
+        let coin = Controls {
+            coin: true,
+            ..Controls::default()
+        };
+        for frame in 0..100 {
+            session
+                .frame(if frame % 7 == 0 {
+                    coin
+                } else {
+                    Controls::default()
+                })
+                .unwrap();
+        }
+        session.reset();
+        for frame in 0..200 {
+            session
+                .frame(if frame % 5 == 0 {
+                    coin
+                } else {
+                    Controls::default()
+                })
+                .unwrap();
+        }
+        for _ in 0..30 {
+            session.rewind();
+        }
+        for _ in 0..50 {
+            session.frame(coin).unwrap();
+        }
+        let state = session.machine.save_state();
+
+        assert_eq!(movie.len(), 220);
+        let replayed = movie.play(&rom, |_| {}).unwrap();
+        assert_eq!(replayed.save_state(), state);
+    }
+}
@@ -0,0 +1,187 @@
+/// samples a second
+pub const SAMPLE_RATE: u32 = 44100;
+/// samples a frame at 60 frames a second
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// the cabinet's sounds come from analogue circuits and, on later boards, samples,
+/// none of which is in the rom, so these are rough imitations made of square
+/// waves and noise, started by the bits the game writes to ports 3 and 5
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Effect {
+    /// the warbling saucer, held while its bit is
+    Saucer,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraShip,
+    /// one of the four notes of the fleet marching
+    Fleet(usize),
+    SaucerHit,
+}
+
+impl Effect {
+    /// how long it plays, in seconds, the saucer plays for as long as it is held
+    fn length(self) -> f32 {
+        match self {
+            Effect::Saucer => f32::INFINITY,
+            Effect::Shot => 0.3,
+            Effect::PlayerDeath => 1.0,
+            Effect::InvaderDeath => 0.3,
+            Effect::ExtraShip => 0.5,
+            Effect::Fleet(_) => 0.1,
+            Effect::SaucerHit => 0.6,
+        }
+    }
+
+    /// the pitch at `t` seconds in, none for noise
+    fn pitch(self, t: f32) -> Option<f32> {
+        let warble = |rate: f32| (t * rate * std::f32::consts::TAU).sin();
+        match self {
+            Effect::Saucer => Some(800.0 + 250.0 * warble(7.0)),
+            Effect::Shot => Some(1200.0 - 2500.0 * t),
+            Effect::PlayerDeath | Effect::InvaderDeath => None,
+            Effect::ExtraShip => Some(1500.0),
+            Effect::Fleet(note) => Some([98.0, 87.0, 78.0, 73.0][note]),
+            Effect::SaucerHit => Some(400.0 + 150.0 * warble(15.0)),
+        }
+    }
+
+    fn volume(self, t: f32) -> f32 {
+        let fade = 1.0 - t / self.length();
+        match self {
+            Effect::Saucer => 0.15,
+            // three beeps
+            Effect::ExtraShip => 0.2 * ((t * 6.0).fract() < 0.5) as u8 as f32,
+            Effect::Fleet(_) => 0.4 * fade,
+            _ => 0.25 * fade,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Voice {
+    effect: Effect,
+    /// samples played
+    age: u32,
+    /// cycles of the square wave played
+    phase: f32,
+}
+
+/// turns the sound latches, frame by frame, into samples
+#[derive(Debug)]
+pub struct Synth {
+    latches: [u8; 2],
+    voices: Vec<Voice>,
+    noise: u32,
+}
+
+impl Synth {
+    pub fn new() -> Self {
+        Synth {
+            latches: [0; 2],
+            voices: Vec::new(),
+            noise: 1,
+        }
+    }
+
+    /// the samples of a frame that ended with the sound latches at `sound`
+    pub fn frame(&mut self, sound: [u8; 2]) -> Vec<i16> {
+        let effects = [
+            (0, 0, Effect::Saucer),
+            (0, 1, Effect::Shot),
+            (0, 2, Effect::PlayerDeath),
+            (0, 3, Effect::InvaderDeath),
+            (0, 4, Effect::ExtraShip),
+            (1, 0, Effect::Fleet(0)),
+            (1, 1, Effect::Fleet(1)),
+            (1, 2, Effect::Fleet(2)),
+            (1, 3, Effect::Fleet(3)),
+            (1, 4, Effect::SaucerHit),
+        ];
+        for (port, bit, effect) in effects {
+            let was = self.latches[port] & (1 << bit) != 0;
+            let is = sound[port] & (1 << bit) != 0;
+            if is && !was {
+                self.voices.push(Voice {
+                    effect,
+                    age: 0,
+                    phase: 0.0,
+                });
+            }
+        }
+        // the saucer stops when its bit goes
+        if sound[0] & 1 == 0 {
+            self.voices.retain(|voice| voice.effect != Effect::Saucer);
+        }
+        self.latches = sound;
+
+        // bit 5 of port 3 turns the amplifier on, it is off in the attract mode
+        let on = sound[0] & (1 << 5) != 0;
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        for _ in 0..SAMPLES_PER_FRAME {
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 17;
+            self.noise ^= self.noise << 5;
+            let noise = (self.noise & 1) as f32 * 2.0 - 1.0;
+
+            let mut mix = 0.0;
+            for voice in &mut self.voices {
+                let t = voice.age as f32 / SAMPLE_RATE as f32;
+                let wave = match voice.effect.pitch(t) {
+                    Some(pitch) => {
+                        voice.phase += pitch / SAMPLE_RATE as f32;
+                        if voice.phase.fract() < 0.5 {
+                            1.0
+                        } else {
+                            -1.0
+                        }
+                    }
+                    None => noise,
+                };
+                mix += wave * voice.effect.volume(t);
+                voice.age += 1;
+            }
+            let level = if on { mix.clamp(-1.0, 1.0) } else { 0.0 };
+            samples.push((level * i16::MAX as f32) as i16);
+        }
+        self.voices
+            .retain(|voice| (voice.age as f32 / SAMPLE_RATE as f32) < voice.effect.length());
+        samples
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Synth, SAMPLES_PER_FRAME};
+
+    const AMP: u8 = 1 << 5;
+    const SHOT: u8 = 1 << 1;
+
+    #[test]
+    fn test_effects_start_on_rising_edges() {
+        let mut synth = Synth::new();
+        let silent = |samples: &[i16]| samples.iter().all(|&sample| sample == 0);
+        let frame = synth.frame([AMP, 0]);
+        assert_eq!(frame.len(), SAMPLES_PER_FRAME);
+        assert!(silent(&frame));
+
+        assert!(!silent(&synth.frame([AMP | SHOT, 0])));
+        // held, it plays out once and stops
+        for _ in 0..30 {
+            synth.frame([AMP | SHOT, 0]);
+        }
+        assert!(silent(&synth.frame([AMP | SHOT, 0])));
+        assert!(silent(&synth.frame([AMP, 0])));
+        assert!(!silent(&synth.frame([AMP | SHOT, 0])));
+
+        // nothing with the amplifier off
+        let mut synth = Synth::new();
+        assert!(silent(&synth.frame([SHOT, 0x0F])));
+    }
+}
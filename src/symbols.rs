@@ -0,0 +1,198 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use crate::instructions::Instruction;
+
+/// addresses further than this past the nearest symbol are not described relative to it
+const MAX_OFFSET: u16 = 0x400;
+
+/// names for addresses, loaded from a symbol file
+///
+/// three kinds of lines are understood, anything else is skipped so whole assembler
+/// listings can be loaded:
+///
+/// - `1439 DrawSimpSprite`, an address and a name, the address may start with `0x` or `$`
+/// - `DrawSimpSprite EQU 1439H` or `DrawSimpSprite = 0x1439`, an assembler equate
+/// - `1439  C5    DrawSimpSprite: PUSH B`, a listing line with a label ending in `:`
+///
+/// comments start with `;` or `#`
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut symbols = Symbols::new();
+        for line in text.lines() {
+            let line = line.split([';', '#']).next().unwrap_or("");
+            if let Some((address, name)) = parse_line(line) {
+                symbols.insert(address, name);
+            }
+        }
+        symbols
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Symbols::parse(&fs::read_to_string(path)?))
+    }
+
+    /// add a name, the first name given to an address is the one shown for it
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// the name of exactly this address
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// the nearest symbol at or below `address` and the offset from it
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        let (&start, name) = self.names.range(..=address).next_back()?;
+        let offset = address - start;
+        (offset <= MAX_OFFSET).then_some((name.as_str(), offset))
+    }
+
+    /// `Name` or `Name+0x12` for an address, or the bare hex address
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{:#X}", name, offset),
+            None => format!("{:#06X}", address),
+        }
+    }
+
+    /// the instruction as shown by `Display`, with its address operand replaced by a name
+    pub fn instruction(&self, instruction: &Instruction) -> String {
+        let text = instruction.to_string();
+        match instruction
+            .operand16()
+            .and_then(|address| Some((address, self.name(address)?)))
+        {
+            Some((address, name)) => text.replace(&format!("{:#06X}", address), name),
+            None => text,
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u16, &str)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [name, equ, value, ..] if equ.eq_ignore_ascii_case("equ") || *equ == "=" => {
+            let name = name.trim_end_matches(':');
+            is_name(name).then_some(())?;
+            Some((parse_number(value)?, name))
+        }
+        [address, name] if is_name(name) && !is_bytes(name) => Some((parse_number(address)?, name)),
+        [address, rest @ ..] => {
+            let label = rest
+                .iter()
+                .find_map(|word| word.strip_suffix(':').filter(|label| is_name(label)))?;
+            Some((u16::from_str_radix(address, 16).ok()?, label))
+        }
+        _ => None,
+    }
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// instruction bytes in a listing, like `C5` or `CD3914`
+fn is_bytes(word: &str) -> bool {
+    word.len().is_multiple_of(2) && word.len() <= 8 && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// hex with an optional `0x` or `$` prefix or `h` suffix
+fn parse_number(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .or_else(|| text.strip_suffix(['h', 'H']))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbols;
+    use crate::instructions::Instruction;
+
+    #[test]
+    fn test_parse_formats() {
+        let symbols = Symbols::parse(
+            "\
+; Space Invaders
+1439 DrawSimpSprite
+0x1A5C ClearScreen   ; clears video ram
+$08F3 PrintMessage
+ISR_Vblank: EQU 0010H
+wavesCleared = $2067
+1A32  1A        BlockCopy:  LDAX D
+1A33  77                    MOV  M,A
+not a symbol line at all
+",
+        );
+        assert_eq!(symbols.len(), 6);
+        assert_eq!(symbols.name(0x1439), Some("DrawSimpSprite"));
+        assert_eq!(symbols.address("ClearScreen"), Some(0x1A5C));
+        assert_eq!(symbols.address("PrintMessage"), Some(0x08F3));
+        assert_eq!(symbols.name(0x0010), Some("ISR_Vblank"));
+        assert_eq!(symbols.address("wavesCleared"), Some(0x2067));
+        assert_eq!(symbols.name(0x1A32), Some("BlockCopy"));
+        assert_eq!(symbols.name(0x1A33), None);
+    }
+
+    #[test]
+    fn test_describe_and_instruction() {
+        let symbols = Symbols::parse("1439 DrawSimpSprite\n1A5C ClearScreen");
+        assert_eq!(symbols.describe(0x1439), "DrawSimpSprite");
+        assert_eq!(symbols.describe(0x1442), "DrawSimpSprite+0x9");
+        assert_eq!(symbols.describe(0x0100), "0x0100");
+        assert_eq!(symbols.describe(0x3000), "0x3000");
+        assert_eq!(
+            symbols.instruction(&Instruction::CALL_ADR(0x1A5C)),
+            "CALL ClearScreen"
+        );
+        assert_eq!(
+            symbols.instruction(&Instruction::JMP_ADR(0x1A5D)),
+            "JMP 0x1A5D"
+        );
+    }
+
+    #[test]
+    fn test_bundled_invaders_symbols() {
+        let symbols = Symbols::load("rom/invaders.sym").unwrap();
+        assert_eq!(symbols.address("ISR_Vblank"), Some(0x0010));
+        assert_eq!(symbols.address("DrawSimpSprite"), Some(0x1439));
+    }
+}
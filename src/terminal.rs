@@ -0,0 +1,370 @@
+use std::{fmt::Write as _, str::FromStr};
+
+use crate::{
+    machine::Controls,
+    screen::{self, HEIGHT, WIDTH},
+};
+
+/// keys, shown under the picture
+pub const KEYS: &str = "c coin  1 2 start  arrows space p1  a d s p2  t tilt  \
+backspace rewind  p pause  . step  F3 reset  q quit";
+
+/// how character cells show the picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    /// ▀ ▄ █, a column of two pixels per cell, 224 by 128 cells
+    HalfBlocks,
+    /// braille dots, two columns of four pixels per cell, 112 by 64 cells
+    Braille,
+}
+
+impl Glyphs {
+    /// pixels per cell across and down
+    fn cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlocks => (1, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+
+    /// the character for a cell, `lit` tells if the pixel at a column and row of
+    /// the cell is on
+    fn glyph(self, lit: impl Fn(usize, usize) -> bool) -> char {
+        match self {
+            Glyphs::HalfBlocks => match (lit(0, 0), lit(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            Glyphs::Braille => {
+                // dots 1 to 3 run down the left column, 4 to 6 down the right,
+                // then 7 and 8 below them
+                const DOTS: [[u32; 4]; 2] = [[0, 1, 2, 6], [3, 4, 5, 7]];
+                let mut bits = 0;
+                for (x, column) in DOTS.iter().enumerate() {
+                    for (y, dot) in column.iter().enumerate() {
+                        if lit(x, y) {
+                            bits |= 1 << dot;
+                        }
+                    }
+                }
+                if bits == 0 {
+                    ' '
+                } else {
+                    char::from_u32(0x2800 + bits).unwrap()
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "blocks" => Ok(Glyphs::HalfBlocks),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!(
+                "unknown glyphs: {}, expected blocks or braille",
+                name
+            )),
+        }
+    }
+}
+
+/// a picture from `screen::render` as lines of text, one per row of cells, lit
+/// cells coloured like the overlay with 24 bit ANSI colours
+pub fn render(pixels: &[u8], glyphs: Glyphs) -> Vec<String> {
+    let (cell_width, cell_height) = glyphs.cell();
+    let mut lines = Vec::with_capacity(HEIGHT / cell_height);
+    for top in (0..HEIGHT).step_by(cell_height) {
+        let mut line = String::new();
+        let mut colour = None;
+        for left in (0..WIDTH).step_by(cell_width) {
+            let glyph = glyphs.glyph(|x, y| pixels[(top + y) * WIDTH + left + x] != 0);
+            // the strips line up with cells, so one colour does a whole cell
+            let overlay = screen::overlay(left, top);
+            if glyph != ' ' && colour != Some(overlay) {
+                let [r, g, b] = overlay;
+                write!(line, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+                colour = Some(overlay);
+            }
+            line.push(glyph);
+        }
+        line.push_str("\x1b[0m");
+        lines.push(line);
+    }
+    lines
+}
+
+/// the controls held down, and the rewind key, worked out from key presses alone
+/// when the terminal does not report releases
+#[derive(Debug)]
+pub struct Held {
+    releases: bool,
+    /// frames left for each control, in `Controls::bits` order, then for `REWIND`
+    frames: [u32; 11],
+}
+
+impl Held {
+    /// frames a first press holds its control, about until the key starts repeating
+    const DELAY: u32 = 30;
+    /// frames each repeat keeps it held
+    const REPEAT: u32 = 4;
+    /// the slot after the controls, for the key that rewinds
+    pub const REWIND: usize = 10;
+
+    /// `releases` if the terminal reports key releases
+    pub fn new(releases: bool) -> Self {
+        Held {
+            releases,
+            frames: [0; 11],
+        }
+    }
+
+    /// a press or repeat of the key for control `bit`, or `REWIND`
+    pub fn press(&mut self, bit: usize) {
+        let frames = &mut self.frames[bit];
+        *frames = if self.releases {
+            u32::MAX
+        } else if *frames > 0 {
+            (*frames).max(Self::REPEAT)
+        } else {
+            Self::DELAY
+        };
+    }
+
+    pub fn release(&mut self, bit: usize) {
+        self.frames[bit] = 0;
+    }
+
+    pub fn is_held(&self, bit: usize) -> bool {
+        self.frames[bit] > 0
+    }
+
+    pub fn controls(&self) -> Controls {
+        let bits = self.frames[..Self::REWIND].iter().enumerate();
+        Controls::from_bits(bits.fold(0, |bits, (i, &frames)| bits | ((frames > 0) as u16) << i))
+    }
+
+    /// count a frame off the presses
+    pub fn tick(&mut self) {
+        if !self.releases {
+            for frames in &mut self.frames {
+                *frames = frames.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "terminal")]
+pub use play::run;
+
+#[cfg(feature = "terminal")]
+mod play {
+    use std::{
+        io::{self, Write},
+        time::Duration,
+    };
+
+    use crossterm::{
+        cursor::{Hide, MoveTo, Show},
+        event::{
+            self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+            PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+        },
+        execute, queue,
+        style::Print,
+        terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    };
+
+    use super::{render, Glyphs, Held, KEYS};
+    use crate::{machine::Pacer, screen, session::Session};
+
+    /// play the game in the terminal until q is pressed, leaving the terminal as it
+    /// was found however that happens
+    pub fn run(session: &mut Session, glyphs: Glyphs) -> io::Result<()> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        let result = play(&mut out, session, glyphs, releases);
+
+        if releases {
+            execute!(out, PopKeyboardEnhancementFlags)?;
+        }
+        execute!(out, Show, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn play(
+        out: &mut impl Write,
+        session: &mut Session,
+        glyphs: Glyphs,
+        releases: bool,
+    ) -> io::Result<()> {
+        let mut held = Held::new(releases);
+        let mut paused = false;
+        let mut pacer = Pacer::new();
+        // what is on the terminal, only lines that change are sent again
+        let mut shown: Vec<String> = Vec::new();
+
+        loop {
+            let mut step = false;
+            while event::poll(Duration::ZERO)? {
+                let key = match event::read()? {
+                    Event::Key(key) => key,
+                    Event::Resize(..) => {
+                        queue!(out, Clear(ClearType::All))?;
+                        shown.clear();
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if key.kind == KeyEventKind::Release {
+                    if let Some(bit) = control(key.code) {
+                        held.release(bit);
+                    }
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    // raw mode leaves ctrl-c to us
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(())
+                    }
+                    KeyCode::Char('p') if key.kind == KeyEventKind::Press => paused = !paused,
+                    KeyCode::Char('.') => step = true,
+                    KeyCode::F(3) => session.reset(),
+                    code => {
+                        if let Some(bit) = control(code) {
+                            held.press(bit);
+                        }
+                    }
+                }
+            }
+
+            if held.is_held(Held::REWIND) {
+                session.rewind();
+            } else if !paused || step {
+                session.frame(held.controls())?;
+            }
+            held.tick();
+
+            let machine = &session.machine;
+            let lines = render(&screen::render(machine.video_ram()), glyphs);
+            for (row, line) in lines.iter().enumerate() {
+                if shown.get(row) != Some(line) {
+                    queue!(out, MoveTo(0, row as u16), Print(line))?;
+                }
+            }
+            let status = format!(
+                "frame {}  pc {:04X}{}",
+                machine.frame(),
+                machine.cpu.pc,
+                if paused { "  paused" } else { "" }
+            );
+            queue!(
+                out,
+                MoveTo(0, lines.len() as u16),
+                Print(status),
+                Clear(ClearType::UntilNewLine),
+                MoveTo(0, lines.len() as u16 + 1),
+                Print(KEYS)
+            )?;
+            out.flush()?;
+            shown = lines;
+            pacer.wait();
+        }
+    }
+
+    /// the `Controls::bits` bit a key works, or `Held::REWIND`
+    fn control(code: KeyCode) -> Option<usize> {
+        let bit = match code {
+            KeyCode::Char(c) => match c.to_ascii_lowercase() {
+                'c' => 0,
+                '1' => 1,
+                '2' => 2,
+                ' ' => 3,
+                's' => 6,
+                'a' => 7,
+                'd' => 8,
+                't' => 9,
+                _ => return None,
+            },
+            KeyCode::Left => 4,
+            KeyCode::Right => 5,
+            KeyCode::Backspace => Held::REWIND,
+            _ => return None,
+        };
+        Some(bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Glyphs, Held};
+    use crate::screen::{HEIGHT, WIDTH};
+
+    #[test]
+    fn test_render() {
+        let mut pixels = vec![0; WIDTH * HEIGHT];
+        // top left pixel, and the one below it
+        pixels[0] = 0xFF;
+        pixels[WIDTH] = 0xFF;
+        // bottom right pixel of the first braille cell in the red strip
+        pixels[35 * WIDTH + 1] = 0xFF;
+
+        let lines = render(&pixels, Glyphs::HalfBlocks);
+        assert_eq!(lines.len(), 128);
+        assert!(lines[0].starts_with("\x1b[38;2;255;255;255m█ "));
+        assert_eq!(lines[1], format!("{}\x1b[0m", " ".repeat(WIDTH)));
+
+        let lines = render(&pixels, Glyphs::Braille);
+        assert_eq!(lines.len(), 64);
+        assert!(lines[0].starts_with("\x1b[38;2;255;255;255m⠃ "));
+        assert!(lines[8].starts_with("\x1b[38;2;255;48;48m⢀ "));
+        assert_eq!("braille".parse(), Ok(Glyphs::Braille));
+    }
+
+    #[test]
+    fn test_held() {
+        // without releases a press holds until the key repeats
+        let mut held = Held::new(false);
+        held.press(4);
+        for _ in 0..Held::DELAY {
+            assert!(held.controls().p1_left);
+            held.tick();
+        }
+        assert!(!held.controls().p1_left);
+        held.press(4);
+        held.tick();
+        held.press(4);
+        assert!(held.controls().p1_left);
+
+        // with them it holds until the release
+        let mut held = Held::new(true);
+        held.press(3);
+        for _ in 0..1000 {
+            held.tick();
+        }
+        assert!(held.controls().p1_fire);
+        held.release(3);
+        assert!(!held.controls().p1_fire);
+
+        // rewinding is held the same way but is not a control
+        held.press(Held::REWIND);
+        assert!(held.is_held(Held::REWIND));
+        assert_eq!(held.controls().bits(), 0);
+    }
+}
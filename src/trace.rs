@@ -0,0 +1,408 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use crate::{cpu::Cpu, machine::Machine, symbols::Symbols};
+
+/// writes one line per instruction, in the format printed by many other 8080 emulators
+/// so traces can be compared with `diff`, the registers and cycles separated by
+/// commas and the bytes and the mnemonic each after a tab:
+///
+/// `PC: 0100, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000, CYC: 0<tab>(C3 A5 01 00)<tab>JMP 0x01A5`
+///
+/// registers and cycles are the state before the instruction executes, AF is the
+/// accumulator and the flags packed as pushed by PUSH PSW, and the bytes in
+/// parentheses are the four bytes starting at PC
+pub struct Tracer<W: Write> {
+    out: W,
+    symbols: Option<Symbols>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Tracer { out, symbols: None }
+    }
+
+    /// name the addresses in the mnemonic column, the other columns are unchanged
+    /// so the trace can still be compared with others
+    pub fn with_symbols(out: W, symbols: Symbols) -> Self {
+        Tracer {
+            out,
+            symbols: Some(symbols),
+        }
+    }
+
+    /// write the line for the instruction at the program counter, call before executing it
+    pub fn trace(&mut self, cpu: &Cpu) -> io::Result<()> {
+        match &self.symbols {
+            Some(symbols) => writeln!(self.out, "{}", line_with_symbols(cpu, symbols)),
+            None => writeln!(self.out, "{}", line(cpu)),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// format the trace line for the instruction at the program counter
+pub fn line(cpu: &Cpu) -> String {
+    format_line(cpu, cpu.fetch().0.to_string())
+}
+
+/// format the trace line with named addresses in the mnemonic column
+pub fn line_with_symbols(cpu: &Cpu, symbols: &Symbols) -> String {
+    format_line(cpu, symbols.instruction(&cpu.fetch().0))
+}
+
+fn format_line(cpu: &Cpu, instruction: String) -> String {
+    let pc = cpu.pc;
+    let bytes: Vec<u8> = (0..4).map(|i| cpu.read_byte(pc.wrapping_add(i))).collect();
+    format!(
+        "PC: {:04X}, AF: {:04X}, BC: {:04X}, DE: {:04X}, HL: {:04X}, SP: {:04X}, CYC: {}\t({:02X} {:02X} {:02X} {:02X})\t{}",
+        pc,
+        u16::from_be_bytes([cpu.a, cpu.psw()]),
+        cpu.bc(),
+        cpu.de(),
+        cpu.hl(),
+        cpu.sp,
+        cpu.cycles(),
+        bytes[0],
+        bytes[1],
+        bytes[2],
+        bytes[3],
+        instruction,
+    )
+}
+
+/// the comparable fields of a trace line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceLine {
+    pub pc: u16,
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub cycles: Option<u64>,
+}
+
+impl TraceLine {
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        TraceLine {
+            pc: cpu.pc,
+            af: u16::from_be_bytes([cpu.a, cpu.psw()]),
+            bc: cpu.bc(),
+            de: cpu.de(),
+            hl: cpu.hl(),
+            sp: cpu.sp,
+            cycles: Some(cpu.cycles()),
+        }
+    }
+
+    /// parse the `KEY: value` fields of a line, as written by `Tracer` or another emulator,
+    /// returns `None` if any register is missing, the cycle count is optional
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut pc = None;
+        let mut af = None;
+        let mut bc = None;
+        let mut de = None;
+        let mut hl = None;
+        let mut sp = None;
+        let mut cycles = None;
+        let fields = line.split('\t').next().unwrap_or("");
+        for field in fields.split(',') {
+            let (key, value) = match field.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            let register = || u16::from_str_radix(value, 16).ok();
+            match key {
+                "PC" => pc = register(),
+                "AF" => af = register(),
+                "BC" => bc = register(),
+                "DE" => de = register(),
+                "HL" => hl = register(),
+                "SP" => sp = register(),
+                "CYC" => cycles = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(TraceLine {
+            pc: pc?,
+            af: af?,
+            bc: bc?,
+            de: de?,
+            hl: hl?,
+            sp: sp?,
+            cycles,
+        })
+    }
+
+    /// names of the registers that differ, cycle counts are not compared
+    pub fn mismatches(&self, other: &TraceLine) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.pc != other.pc {
+            names.push("PC");
+        }
+        if self.af >> 8 != other.af >> 8 {
+            names.push("A");
+        }
+        if self.af & 0xFF != other.af & 0xFF {
+            names.push("F");
+        }
+        if self.bc != other.bc {
+            names.push("BC");
+        }
+        if self.de != other.de {
+            names.push("DE");
+        }
+        if self.hl != other.hl {
+            names.push("HL");
+        }
+        if self.sp != other.sp {
+            names.push("SP");
+        }
+        names
+    }
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PC: {:04X}, AF: {:04X}, BC: {:04X}, DE: {:04X}, HL: {:04X}, SP: {:04X}, F: {}",
+            self.pc,
+            self.af,
+            self.bc,
+            self.de,
+            self.hl,
+            self.sp,
+            flags_string((self.af & 0xFF) as u8)
+        )?;
+        if let Some(cycles) = self.cycles {
+            write!(f, ", CYC: {}", cycles)?;
+        }
+        Ok(())
+    }
+}
+
+/// the flags of a processor status word as `SZAPC`, with `.` for a clear flag
+pub fn flags_string(psw: u8) -> String {
+    [(7, 'S'), (6, 'Z'), (4, 'A'), (2, 'P'), (0, 'C')]
+        .iter()
+        .map(|&(bit, name)| if psw & (1 << bit) != 0 { name } else { '.' })
+        .collect()
+}
+
+/// the first point where the emulator disagrees with a reference trace
+#[derive(Debug)]
+pub struct Divergence {
+    /// 1-based line number in the reference trace
+    pub line: usize,
+    pub expected: TraceLine,
+    pub actual: TraceLine,
+    /// our trace lines leading up to the divergence, oldest first
+    pub context: Vec<String>,
+    /// the reference lines from the divergence onwards
+    pub reference: Vec<String>,
+    /// the instruction executed just before the divergence, as a trace line,
+    /// `None` if the very first line differs
+    pub offending: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "first divergence at reference line {}", self.line)?;
+        writeln!(f, "emulator trace before it:")?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "reference trace from it:")?;
+        for line in &self.reference {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "expected: {}", self.expected)?;
+        writeln!(f, "actual:   {}", self.actual)?;
+        writeln!(
+            f,
+            "differs:  {}",
+            self.actual.mismatches(&self.expected).join(", ")
+        )?;
+        match &self.offending {
+            Some(line) => write!(f, "offending instruction: {}", line),
+            None => write!(f, "the initial state differs"),
+        }
+    }
+}
+
+/// run `machine` in lockstep with a reference trace and stop at the first line whose
+/// PC, registers or flags differ, keeping `context` lines of our own trace before it,
+/// which may be none
+pub fn diff<M: Machine, R: BufRead>(
+    machine: &mut M,
+    reference: R,
+    context: usize,
+) -> io::Result<Option<Divergence>> {
+    let mut recent: VecDeque<String> = VecDeque::with_capacity(context);
+    // the offending instruction is kept even without context
+    let mut previous = None;
+    let mut lines = reference.lines().enumerate();
+    while let Some((number, text)) = lines.next() {
+        let text = text?;
+        let expected = match TraceLine::parse(&text) {
+            Some(expected) => expected,
+            None => continue,
+        };
+        let actual = TraceLine::from_cpu(machine.cpu());
+        if !actual.mismatches(&expected).is_empty() {
+            let mut after = vec![text];
+            for (_, text) in lines.take(context) {
+                after.push(text?);
+            }
+            return Ok(Some(Divergence {
+                line: number + 1,
+                expected,
+                actual,
+                reference: after,
+                offending: previous,
+                context: recent.into(),
+            }));
+        }
+        let current = line(machine.cpu());
+        if context > 0 {
+            if recent.len() == context {
+                recent.pop_front();
+            }
+            recent.push_back(current.clone());
+        }
+        previous = Some(current);
+        machine.step();
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, flags_string, line, TraceLine, Tracer};
+    use crate::{cpu::Cpu, symbols::Symbols};
+
+    #[test]
+    fn test_line_format() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x31, 0x00, 0x24, 0xC3, 0xA5, 0x01], 0);
+        assert_eq!(
+            line(&cpu),
+            "PC: 0000, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000, CYC: 0\t(31 00 24 C3)\tLXI SP,0x2400"
+        );
+        cpu.step();
+        assert_eq!(
+            line(&cpu),
+            "PC: 0003, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 2400, CYC: 10\t(C3 A5 01 00)\tJMP 0x01A5"
+        );
+    }
+
+    #[test]
+    fn test_tracer_writes_one_line_per_instruction() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x06, 0x42, 0x04, 0x37], 0);
+        let mut tracer = Tracer::new(Vec::new());
+        for _ in 0..3 {
+            tracer.trace(&cpu).unwrap();
+            cpu.step();
+        }
+        let out = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("\tMVI B,0x42"));
+        assert!(lines[1].starts_with("PC: 0002, AF: 0002, BC: 4200,"));
+        assert!(lines[1].ends_with("\tINR B"));
+        assert!(lines[2].starts_with("PC: 0003, AF: 0002, BC: 4300,"));
+        assert!(lines[2].ends_with("CYC: 12\t(37 00 00 00)\tSTC"));
+    }
+
+    #[test]
+    fn test_tracer_with_symbols() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0xC3, 0x03, 0x00, 0x76], 0);
+        let symbols = Symbols::parse("0003 Done");
+        let mut tracer = Tracer::with_symbols(Vec::new(), symbols);
+        tracer.trace(&cpu).unwrap();
+        let out = String::from_utf8(tracer.into_inner()).unwrap();
+        assert!(out.starts_with("PC: 0000, AF: 0002,"));
+        assert!(out.ends_with("\t(C3 03 00 76)\tJMP Done\n"));
+    }
+
+    fn program() -> Cpu {
+        // MVI A,0x0F; ADI 0x01; STC; CMC; JMP 0x0000
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x3E, 0x0F, 0xC6, 0x01, 0x37, 0x3F, 0xC3, 0x00, 0x00], 0);
+        cpu
+    }
+
+    fn reference(steps: usize) -> String {
+        let mut cpu = program();
+        let mut tracer = Tracer::new(Vec::new());
+        for _ in 0..steps {
+            tracer.trace(&cpu).unwrap();
+            cpu.step();
+        }
+        String::from_utf8(tracer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let parsed = TraceLine::parse(
+            "PC: 0ADA, AF: 4002, BC: 0000, DE: 1FB0, HL: 3E01, SP: 23FE, CYC: 386410\t(3A C0 20 A7)",
+        )
+        .unwrap();
+        assert_eq!(parsed.pc, 0x0ADA);
+        assert_eq!(parsed.af, 0x4002);
+        assert_eq!(parsed.de, 0x1FB0);
+        assert_eq!(parsed.cycles, Some(386410));
+        // no cycle count is fine, missing registers are not
+        assert!(
+            TraceLine::parse("PC: 0000, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000")
+                .is_some()
+        );
+        assert!(TraceLine::parse("PC: 0000, AF: 0002").is_none());
+        assert_eq!(flags_string(0x57), ".ZAPC");
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let mut cpu = program();
+        let reference = reference(20);
+        assert!(diff(&mut cpu, reference.as_bytes(), 3).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_diff_finds_first_divergence() {
+        let reference = reference(20).replace("AF: 1012", "AF: 1013");
+        let mut cpu = program();
+        let divergence = diff(&mut cpu, reference.as_bytes(), 2).unwrap().unwrap();
+        // the reference claims ADI 0x01 set carry
+        assert_eq!(divergence.line, 3);
+        assert_eq!(
+            divergence.actual.mismatches(&divergence.expected),
+            vec!["F"]
+        );
+        assert_eq!(divergence.context.len(), 2);
+        assert_eq!(divergence.reference.len(), 3);
+        assert!(divergence.offending.unwrap().ends_with("\tADI 0x01"));
+
+        // no context at all, but still the offending instruction
+        let mut cpu = program();
+        let divergence = diff(&mut cpu, reference.as_bytes(), 0).unwrap().unwrap();
+        assert_eq!(divergence.line, 3);
+        assert!(divergence.context.is_empty());
+        assert_eq!(divergence.reference.len(), 1);
+        assert!(divergence.offending.unwrap().ends_with("\tADI 0x01"));
+    }
+}
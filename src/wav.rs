@@ -0,0 +1,63 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// writes 16 bit mono PCM as a WAV file, the sizes in the header are filled in
+/// by `finish`
+pub struct Wav<W: Write + Seek> {
+    writer: W,
+    bytes: u32,
+}
+
+impl<W: Write + Seek> Wav<W> {
+    /// write the header for `rate` samples a second
+    pub fn new(mut writer: W, rate: u32) -> io::Result<Self> {
+        writer.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&rate.to_le_bytes())?;
+        writer.write_all(&(rate * 2).to_le_bytes())?;
+        // bytes per sample, bits per sample
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data\0\0\0\0")?;
+        Ok(Wav { writer, bytes: 0 })
+    }
+
+    pub fn samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.writer.write_all(&bytes)?;
+        self.bytes += bytes.len() as u32;
+        Ok(())
+    }
+
+    /// fill in the sizes
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + self.bytes).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.bytes.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Wav;
+
+    #[test]
+    fn test_header() {
+        let mut wav = Wav::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.samples(&[0, 1, -1]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 6);
+        assert_eq!(data[..8], *b"RIFF\x2A\0\0\0");
+        assert_eq!(data[24..28], 44100u32.to_le_bytes());
+        assert_eq!(data[36..44], *b"data\x06\0\0\0");
+        assert_eq!(data[44..], [0, 0, 1, 0, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::error::Error;
+
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
+
+use crate::{
+    crt::{Crt, Settings},
+    machine::{Controls, Invaders, Pacer},
+    screen,
+    session::Session,
+};
+
+/// keys, printed at startup
+pub const KEYS: &str = "\
+C coin, 1 and 2 start, arrows and space player 1, A D and S player 2, T tilt
+Backspace rewind, P pause, F3 reset, F4 aspect correction, F11 fullscreen, Esc quit";
+
+/// settings for `run`
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// window pixels per screen pixel to start with
+    pub scale: usize,
+    /// squeeze the picture to the shape of the cabinet's monitor
+    pub aspect: bool,
+    pub fullscreen: bool,
+    /// size of the display to cover when fullscreen, there is no portable way to ask
+    pub display: (usize, usize),
+    pub filter: Settings,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scale: 2,
+            aspect: false,
+            fullscreen: false,
+            display: (1920, 1080),
+            filter: Settings::default(),
+        }
+    }
+}
+
+/// play the game in a window, drawn in software, until it is closed
+pub fn run(session: &mut Session, mut options: Options) -> Result<(), Box<dyn Error>> {
+    let mut window = open(&options)?;
+    let mut paused = false;
+    let mut pacer = Pacer::new();
+    let mut crt = Crt::new(options.filter);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            paused = !paused;
+        }
+        if window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            session.reset();
+        }
+        if window.is_key_pressed(Key::F4, KeyRepeat::No) {
+            options.aspect = !options.aspect;
+        }
+        if window.is_key_pressed(Key::F11, KeyRepeat::No) {
+            options.fullscreen = !options.fullscreen;
+            window = open(&options)?;
+        }
+
+        if window.is_key_down(Key::Backspace) {
+            session.rewind();
+        } else if !paused {
+            session.frame(controls(&window))?;
+        }
+        draw(&mut window, &session.machine, &mut crt, options.aspect)?;
+        pacer.wait();
+    }
+    Ok(())
+}
+
+/// a window sized for the picture, or a borderless one covering the display
+fn open(options: &Options) -> Result<Window, minifb::Error> {
+    let (width, height) = if options.fullscreen {
+        options.display
+    } else {
+        let width = if options.aspect {
+            screen::CORRECTED_WIDTH
+        } else {
+            screen::WIDTH
+        };
+        (width * options.scale, screen::HEIGHT * options.scale)
+    };
+    let mut window = Window::new(
+        "Space Invaders",
+        width,
+        height,
+        WindowOptions {
+            borderless: options.fullscreen,
+            title: !options.fullscreen,
+            topmost: options.fullscreen,
+            resize: true,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        },
+    )?;
+    // pacing is done by `run`
+    window.set_target_fps(0);
+    Ok(window)
+}
+
+/// the filtered picture at the largest whole scale that fits the window, centred
+/// on black
+fn draw(
+    window: &mut Window,
+    machine: &Invaders,
+    crt: &mut Crt,
+    aspect: bool,
+) -> Result<(), minifb::Error> {
+    let (window_width, window_height) = window.get_size();
+    let (_, width, height) = screen::fit(window_width, window_height, aspect);
+    let image = crt.apply(&screen::render(machine.video_ram()), width, height);
+
+    // a window smaller than one scale gets its top left corner
+    let buffer_width = window_width.max(width);
+    let buffer_height = window_height.max(height);
+    let left = (buffer_width - width) / 2;
+    let top = (buffer_height - height) / 2;
+    let mut buffer = vec![0u32; buffer_width * buffer_height];
+    for (y, row) in image.pixels.chunks(width * 3).enumerate() {
+        let start = (top + y) * buffer_width + left;
+        for (out, rgb) in buffer[start..start + width].iter_mut().zip(row.chunks(3)) {
+            *out = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+        }
+    }
+    window.update_with_buffer(&buffer, buffer_width, buffer_height)
+}
+
+fn controls(window: &Window) -> Controls {
+    let down = |key| window.is_key_down(key);
+    Controls {
+        coin: down(Key::C),
+        p1_start: down(Key::Key1),
+        p2_start: down(Key::Key2),
+        p1_fire: down(Key::Space),
+        p1_left: down(Key::Left),
+        p1_right: down(Key::Right),
+        p2_fire: down(Key::S),
+        p2_left: down(Key::A),
+        p2_right: down(Key::D),
+        tilt: down(Key::T),
+    }
+}
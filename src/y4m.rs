@@ -0,0 +1,66 @@
+use std::io::{self, Write};
+
+/// writes uncompressed YUV4MPEG2 video, which ffmpeg and most players read, with
+/// full resolution colour so the overlay's edges stay sharp
+pub struct Y4m<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> Y4m<W> {
+    /// write the header for `fps` frames a second
+    pub fn new(mut writer: W, width: usize, height: usize, fps: u32) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            width, height, fps
+        )?;
+        Ok(Y4m {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    /// add a frame of RGB pixels, row by row from the top
+    pub fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), self.width * self.height * 3);
+        // BT.601 in the usual 16 to 235 range, one plane after another
+        let mut planes = vec![0; rgb.len()];
+        let (y, chroma) = planes.split_at_mut(self.width * self.height);
+        let (u, v) = chroma.split_at_mut(self.width * self.height);
+        for (i, pixel) in rgb.chunks(3).enumerate() {
+            let [r, g, b] = [pixel[0] as i32, pixel[1] as i32, pixel[2] as i32];
+            y[i] = (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8;
+            u[i] = (128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8;
+            v[i] = (128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Y4m;
+
+    #[test]
+    fn test_frames() {
+        let mut video = Y4m::new(Vec::new(), 2, 1, 60).unwrap();
+        video.frame(&[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+        video.frame(&[0xFF, 0, 0, 0, 0xFF, 0]).unwrap();
+        let data = video.finish().unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\n";
+        assert!(data.starts_with(header));
+        let frames = &data[header.len()..];
+        // black and white, then red and green
+        assert_eq!(frames[..12], *b"FRAME\n\x10\xEB\x80\x80\x80\x80");
+        assert_eq!(frames[12..], *b"FRAME\n\x52\x90\x5A\x36\xF0\x22");
+    }
+}
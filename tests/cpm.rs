@@ -0,0 +1,90 @@
+//! runs the standard 8080 exerciser programs under the minimal CP/M in `invaders::cpm`
+//!
+//! the programs are not distributed with the emulator, so their tests are ignored:
+//! put TST8080.COM, 8080PRE.COM, CPUTEST.COM and 8080EXM.COM in `rom/cpm` and run
+//! them with `cargo test --release --test cpm -- --ignored`, a missing one fails.
+//!
+//! each may run a little over the instructions it is known to take on a correct
+//! 8080, so one that loops or runs away fails rather than hanging
+
+use std::{fs, path::PathBuf};
+
+use invaders::cpm::Cpm;
+
+/// run a program to its warm boot, at most `limit` instructions
+fn run(name: &str, limit: u64) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("rom/cpm")
+        .join(name);
+    let program = fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let mut cpm = Cpm::new(&program);
+    let finished = cpm.run(limit);
+    let output = String::from_utf8_lossy(cpm.output()).into_owned();
+    println!("{}", output);
+    assert!(
+        finished,
+        "{} did not finish in {} instructions, output:\n{}",
+        name, limit, output
+    );
+    output
+}
+
+#[test]
+fn test_console_output() {
+    // the two BDOS calls the exercisers print with, from a hand-assembled program
+    let program = [
+        0x0E, 0x09, // 0100 MVI C,9
+        0x11, 0x16, 0x01, // 0102 LXI D,0116
+        0xCD, 0x05, 0x00, // 0105 CALL 0005
+        0x0E, 0x02, // 0108 MVI C,2
+        0x1E, b'K', // 010A MVI E,'K'
+        0xCD, 0x05, 0x00, // 010C CALL 0005
+        0x1E, b'\n', // 010F MVI E,'\n'
+        0xCD, 0x05, 0x00, // 0111 CALL 0005
+        0xC9, // 0114 RET, to the warm boot
+        0x00, // 0115
+        b'O', b'$', // 0116
+    ];
+    let mut cpm = Cpm::new(&program);
+    assert!(cpm.run(100));
+    assert_eq!(cpm.error(), None);
+    assert_eq!(cpm.output(), b"OK\n");
+}
+
+#[test]
+#[ignore = "needs TST8080.COM in rom/cpm"]
+fn test_tst8080() {
+    // 651 instructions
+    let output = run("TST8080.COM", 1_000);
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs 8080PRE.COM in rom/cpm"]
+fn test_8080pre() {
+    // 1,061 instructions
+    let output = run("8080PRE.COM", 2_000);
+    assert!(
+        output.contains("8080 Preliminary tests complete"),
+        "{}",
+        output
+    );
+    assert!(!output.contains("ERROR"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs CPUTEST.COM in rom/cpm"]
+fn test_cputest() {
+    // 33,971,311 instructions
+    let output = run("CPUTEST.COM", 40_000_000);
+    assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs 8080EXM.COM in rom/cpm, and minutes even in release"]
+fn test_8080exm() {
+    // 2,919,050,698 instructions
+    let output = run("8080EXM.COM", 3_200_000_000);
+    assert!(output.contains("Tests complete"), "{}", output);
+    assert!(!output.contains("ERROR"), "{}", output);
+}
@@ -0,0 +1,402 @@
+//! fuzzing of the decoder and the whole program disassembler: arbitrary bytes and
+//! offsets must never panic, sizes must agree with what was consumed, and every
+//! instruction must encode back to the bytes it was decoded from, checked against
+//! a table of the opcodes written out from the 8080 manual rather than the
+//! decoder's own. every opcode must also decode to what that table says
+//!
+//! set `PROPTEST_CASES` to run more cases than the default
+
+use invaders::instructions::{disassemble_all, Instruction};
+use proptest::prelude::*;
+
+/// mnemonic and size of every opcode, from the 8080 manual, with `d8`, `d16` and
+/// `a16` for operands and `*` before an undocumented opcode and what it aliases
+const OPCODES: [(&str, usize); 256] = [
+    // 0x00
+    ("NOP", 1),
+    ("LXI B,d16", 3),
+    ("STAX B", 1),
+    ("INX B", 1),
+    ("INR B", 1),
+    ("DCR B", 1),
+    ("MVI B,d8", 2),
+    ("RLC", 1),
+    ("*NOP", 1),
+    ("DAD B", 1),
+    ("LDAX B", 1),
+    ("DCX B", 1),
+    ("INR C", 1),
+    ("DCR C", 1),
+    ("MVI C,d8", 2),
+    ("RRC", 1),
+    // 0x10
+    ("*NOP", 1),
+    ("LXI D,d16", 3),
+    ("STAX D", 1),
+    ("INX D", 1),
+    ("INR D", 1),
+    ("DCR D", 1),
+    ("MVI D,d8", 2),
+    ("RAL", 1),
+    ("*NOP", 1),
+    ("DAD D", 1),
+    ("LDAX D", 1),
+    ("DCX D", 1),
+    ("INR E", 1),
+    ("DCR E", 1),
+    ("MVI E,d8", 2),
+    ("RAR", 1),
+    // 0x20
+    ("*NOP", 1),
+    ("LXI H,d16", 3),
+    ("SHLD a16", 3),
+    ("INX H", 1),
+    ("INR H", 1),
+    ("DCR H", 1),
+    ("MVI H,d8", 2),
+    ("DAA", 1),
+    ("*NOP", 1),
+    ("DAD H", 1),
+    ("LHLD a16", 3),
+    ("DCX H", 1),
+    ("INR L", 1),
+    ("DCR L", 1),
+    ("MVI L,d8", 2),
+    ("CMA", 1),
+    // 0x30
+    ("*NOP", 1),
+    ("LXI SP,d16", 3),
+    ("STA a16", 3),
+    ("INX SP", 1),
+    ("INR M", 1),
+    ("DCR M", 1),
+    ("MVI M,d8", 2),
+    ("STC", 1),
+    ("*NOP", 1),
+    ("DAD SP", 1),
+    ("LDA a16", 3),
+    ("DCX SP", 1),
+    ("INR A", 1),
+    ("DCR A", 1),
+    ("MVI A,d8", 2),
+    ("CMC", 1),
+    // 0x40
+    ("MOV B,B", 1),
+    ("MOV B,C", 1),
+    ("MOV B,D", 1),
+    ("MOV B,E", 1),
+    ("MOV B,H", 1),
+    ("MOV B,L", 1),
+    ("MOV B,M", 1),
+    ("MOV B,A", 1),
+    ("MOV C,B", 1),
+    ("MOV C,C", 1),
+    ("MOV C,D", 1),
+    ("MOV C,E", 1),
+    ("MOV C,H", 1),
+    ("MOV C,L", 1),
+    ("MOV C,M", 1),
+    ("MOV C,A", 1),
+    // 0x50
+    ("MOV D,B", 1),
+    ("MOV D,C", 1),
+    ("MOV D,D", 1),
+    ("MOV D,E", 1),
+    ("MOV D,H", 1),
+    ("MOV D,L", 1),
+    ("MOV D,M", 1),
+    ("MOV D,A", 1),
+    ("MOV E,B", 1),
+    ("MOV E,C", 1),
+    ("MOV E,D", 1),
+    ("MOV E,E", 1),
+    ("MOV E,H", 1),
+    ("MOV E,L", 1),
+    ("MOV E,M", 1),
+    ("MOV E,A", 1),
+    // 0x60
+    ("MOV H,B", 1),
+    ("MOV H,C", 1),
+    ("MOV H,D", 1),
+    ("MOV H,E", 1),
+    ("MOV H,H", 1),
+    ("MOV H,L", 1),
+    ("MOV H,M", 1),
+    ("MOV H,A", 1),
+    ("MOV L,B", 1),
+    ("MOV L,C", 1),
+    ("MOV L,D", 1),
+    ("MOV L,E", 1),
+    ("MOV L,H", 1),
+    ("MOV L,L", 1),
+    ("MOV L,M", 1),
+    ("MOV L,A", 1),
+    // 0x70
+    ("MOV M,B", 1),
+    ("MOV M,C", 1),
+    ("MOV M,D", 1),
+    ("MOV M,E", 1),
+    ("MOV M,H", 1),
+    ("MOV M,L", 1),
+    ("HLT", 1),
+    ("MOV M,A", 1),
+    ("MOV A,B", 1),
+    ("MOV A,C", 1),
+    ("MOV A,D", 1),
+    ("MOV A,E", 1),
+    ("MOV A,H", 1),
+    ("MOV A,L", 1),
+    ("MOV A,M", 1),
+    ("MOV A,A", 1),
+    // 0x80
+    ("ADD B", 1),
+    ("ADD C", 1),
+    ("ADD D", 1),
+    ("ADD E", 1),
+    ("ADD H", 1),
+    ("ADD L", 1),
+    ("ADD M", 1),
+    ("ADD A", 1),
+    ("ADC B", 1),
+    ("ADC C", 1),
+    ("ADC D", 1),
+    ("ADC E", 1),
+    ("ADC H", 1),
+    ("ADC L", 1),
+    ("ADC M", 1),
+    ("ADC A", 1),
+    // 0x90
+    ("SUB B", 1),
+    ("SUB C", 1),
+    ("SUB D", 1),
+    ("SUB E", 1),
+    ("SUB H", 1),
+    ("SUB L", 1),
+    ("SUB M", 1),
+    ("SUB A", 1),
+    ("SBB B", 1),
+    ("SBB C", 1),
+    ("SBB D", 1),
+    ("SBB E", 1),
+    ("SBB H", 1),
+    ("SBB L", 1),
+    ("SBB M", 1),
+    ("SBB A", 1),
+    // 0xA0
+    ("ANA B", 1),
+    ("ANA C", 1),
+    ("ANA D", 1),
+    ("ANA E", 1),
+    ("ANA H", 1),
+    ("ANA L", 1),
+    ("ANA M", 1),
+    ("ANA A", 1),
+    ("XRA B", 1),
+    ("XRA C", 1),
+    ("XRA D", 1),
+    ("XRA E", 1),
+    ("XRA H", 1),
+    ("XRA L", 1),
+    ("XRA M", 1),
+    ("XRA A", 1),
+    // 0xB0
+    ("ORA B", 1),
+    ("ORA C", 1),
+    ("ORA D", 1),
+    ("ORA E", 1),
+    ("ORA H", 1),
+    ("ORA L", 1),
+    ("ORA M", 1),
+    ("ORA A", 1),
+    ("CMP B", 1),
+    ("CMP C", 1),
+    ("CMP D", 1),
+    ("CMP E", 1),
+    ("CMP H", 1),
+    ("CMP L", 1),
+    ("CMP M", 1),
+    ("CMP A", 1),
+    // 0xC0
+    ("RNZ", 1),
+    ("POP B", 1),
+    ("JNZ a16", 3),
+    ("JMP a16", 3),
+    ("CNZ a16", 3),
+    ("PUSH B", 1),
+    ("ADI d8", 2),
+    ("RST 0", 1),
+    ("RZ", 1),
+    ("RET", 1),
+    ("JZ a16", 3),
+    ("*JMP a16", 3),
+    ("CZ a16", 3),
+    ("CALL a16", 3),
+    ("ACI d8", 2),
+    ("RST 1", 1),
+    // 0xD0
+    ("RNC", 1),
+    ("POP D", 1),
+    ("JNC a16", 3),
+    ("OUT d8", 2),
+    ("CNC a16", 3),
+    ("PUSH D", 1),
+    ("SUI d8", 2),
+    ("RST 2", 1),
+    ("RC", 1),
+    ("*RET", 1),
+    ("JC a16", 3),
+    ("IN d8", 2),
+    ("CC a16", 3),
+    ("*CALL a16", 3),
+    ("SBI d8", 2),
+    ("RST 3", 1),
+    // 0xE0
+    ("RPO", 1),
+    ("POP H", 1),
+    ("JPO a16", 3),
+    ("XTHL", 1),
+    ("CPO a16", 3),
+    ("PUSH H", 1),
+    ("ANI d8", 2),
+    ("RST 4", 1),
+    ("RPE", 1),
+    ("PCHL", 1),
+    ("JPE a16", 3),
+    ("XCHG", 1),
+    ("CPE a16", 3),
+    ("*CALL a16", 3),
+    ("XRI d8", 2),
+    ("RST 5", 1),
+    // 0xF0
+    ("RP", 1),
+    ("POP PSW", 1),
+    ("JP a16", 3),
+    ("DI", 1),
+    ("CP a16", 3),
+    ("PUSH PSW", 1),
+    ("ORI d8", 2),
+    ("RST 6", 1),
+    ("RM", 1),
+    ("SPHL", 1),
+    ("JM a16", 3),
+    ("EI", 1),
+    ("CM a16", 3),
+    ("*CALL a16", 3),
+    ("CPI d8", 2),
+    ("RST 7", 1),
+];
+
+/// the bytes an instruction decoded from `bytes` should encode to, the same bytes
+/// except for an undocumented opcode, which becomes the documented one it aliases
+fn canonical(bytes: &[u8]) -> Vec<u8> {
+    let mut expected = bytes.to_vec();
+    if let Some(alias) = OPCODES[bytes[0] as usize].0.strip_prefix('*') {
+        let documented = OPCODES.iter().position(|&(mnemonic, _)| mnemonic == alias);
+        expected[0] = documented.expect("an alias of a documented opcode") as u8;
+    }
+    expected
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(5000))]
+
+    #[test]
+    fn test_disassemble_never_panics(
+        data in proptest::collection::vec(any::<u8>(), 0..8),
+        pc in 0usize..10,
+    ) {
+        match Instruction::disassemble(&data, pc) {
+            Some((instruction, size)) => {
+                prop_assert_eq!(size, instruction.size());
+                prop_assert!(pc + size <= data.len());
+                let bytes = &data[pc..pc + size];
+                prop_assert_eq!(instruction.encode(), canonical(bytes));
+            }
+            // only when the instruction does not fit
+            None => prop_assert!(
+                pc >= data.len()
+                    || Instruction::disassemble(&[&data[pc..], &[0, 0]].concat(), 0)
+                        .is_some_and(|(_, size)| size > data.len() - pc)
+            ),
+        }
+    }
+
+    #[test]
+    fn test_disassemble_all_covers_every_byte(
+        data in proptest::collection::vec(any::<u8>(), 0..512),
+        origin in any::<u16>(),
+    ) {
+        let lines = disassemble_all(&data, origin);
+        let mut offset = 0;
+        for (i, &(address, size, instruction)) in lines.iter().enumerate() {
+            prop_assert_eq!(address, origin.wrapping_add(offset as u16));
+            prop_assert!(size > 0);
+            match instruction {
+                Some(instruction) => {
+                    prop_assert_eq!(size, instruction.size());
+                    let bytes = &data[offset..offset + size];
+                    prop_assert_eq!(instruction.encode(), canonical(bytes));
+                }
+                // a truncated instruction can only be the last line
+                None => prop_assert_eq!(i, lines.len() - 1),
+            }
+            offset += size;
+        }
+        prop_assert_eq!(offset, data.len());
+    }
+}
+
+#[test]
+fn test_decode_table() {
+    for (opcode, &(mnemonic, size)) in OPCODES.iter().enumerate() {
+        let opcode = opcode as u8;
+        let (instruction, decoded_size) = Instruction::disassemble(&[opcode, 0x34, 0x12], 0)
+            .unwrap_or_else(|| panic!("{:02X} does not decode", opcode));
+        let expected = mnemonic
+            .trim_start_matches('*')
+            .replace("d16", "0x1234")
+            .replace("a16", "0x1234")
+            .replace("d8", "0x34");
+        assert_eq!(instruction.to_string(), expected, "opcode {:02X}", opcode);
+        assert_eq!(decoded_size, size, "size of {:02X}", opcode);
+        assert_eq!(instruction.size(), size, "size of {:02X}", opcode);
+        if !mnemonic.starts_with('*') {
+            assert_eq!(instruction.opcode(), opcode, "{}", mnemonic);
+        }
+    }
+}
+
+#[test]
+fn test_every_opcode_round_trips() {
+    for opcode in 0..=255u8 {
+        let bytes = [opcode, 0x34, 0x12];
+        let (instruction, size) = Instruction::disassemble(&bytes, 0).unwrap();
+        assert_eq!(
+            instruction.encode(),
+            canonical(&bytes[..size]),
+            "opcode {:02X}",
+            opcode
+        );
+        // re-decoding the encoding gives the same instruction
+        let encoded = instruction.encode();
+        assert_eq!(
+            Instruction::disassemble(&encoded, 0),
+            Some((instruction, size)),
+            "opcode {:02X}",
+            opcode
+        );
+        // and any shorter slice is refused rather than read past
+        for len in 0..size {
+            assert_eq!(Instruction::disassemble(&bytes[..len], 0), None);
+        }
+    }
+}
+
+#[test]
+fn test_disassemble_rom() {
+    let rom = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders")).unwrap();
+    let lines = disassemble_all(&rom, 0);
+    assert_eq!(lines[0], (0x0000, 1, Some(Instruction::NOP)));
+    let total: usize = lines.iter().map(|&(_, size, _)| size).sum();
+    assert_eq!(total, rom.len());
+}
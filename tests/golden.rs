@@ -0,0 +1,138 @@
+//! boots the real rom and compares rendered frames of the attract mode and the first
+//! wave against checked-in hashes, covering the cpu, the shift register and the
+//! interrupts end to end
+//!
+//! the hashes are self-generated: they come from this emulator, not an independent
+//! one, so they only show that the frames have not changed. what they were taken
+//! from was checked by eye instead, dumping the same frames with `--frames 2001
+//! --dump` and, for the first wave, `--input` with the script below, which also
+//! prints the same hashes: the attract mode types out PLAY and SPACE INVADERS,
+//! plays its demo wave and flips the Y of PLAY, the first wave takes the coin,
+//! shows PLAY PLAYER<1> and then the 55 invaders, four shields and the player's
+//! shots
+//!
+//! after an intended change to what is drawn, regenerate the hashes with
+//! `UPDATE_GOLDENS=1 cargo test --test golden`, which also writes every checked
+//! frame to `target/tmp/golden` as a PNG, and look them over the same way before
+//! committing them, a mismatch writes the frames that differ there too
+
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use invaders::{checksum::crc32, machine::Dips, movie::Movie, png, screen};
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/rom/invaders");
+
+/// insert a coin, start a one player game, then move about and shoot
+const FIRST_WAVE: &str = "\
+100-104 coin
+160-164 p1_start
+400-460 p1_left
+461-560 p1_right
+420-425 p1_fire
+480-485 p1_fire
+540-545 p1_fire
+";
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name))
+}
+
+/// play `movie` and check the frames listed in the golden file `name`, each line a
+/// frame number and the CRC-32 of the rendered frame
+fn check(name: &str, movie: &mut Movie, frames: &[u64]) {
+    let rom = fs::read(ROM).unwrap();
+    let last = *frames.iter().max().unwrap();
+    movie.set_len(last as usize + 1);
+
+    let mut actual = BTreeMap::new();
+    let mut pictures = BTreeMap::new();
+    movie
+        .play(&rom, |machine| {
+            let frame = machine.frame() - 1;
+            if frames.contains(&frame) {
+                let pixels = screen::render(machine.video_ram());
+                actual.insert(frame, crc32(&pixels));
+                pictures.insert(frame, pixels);
+            }
+        })
+        .unwrap();
+
+    let path = golden_path(name);
+    if env::var_os("UPDATE_GOLDENS").is_some() {
+        let text: String = actual
+            .iter()
+            .map(|(frame, hash)| format!("{} {:08X}\n", frame, hash))
+            .collect();
+        fs::write(&path, text).unwrap();
+        for (frame, pixels) in &pictures {
+            write_picture(name, *frame, pixels);
+        }
+        return;
+    }
+
+    let text = fs::read_to_string(&path).unwrap();
+    let expected: BTreeMap<u64, u32> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (frame, hash) = line.split_once(' ').unwrap();
+            (
+                frame.parse().unwrap(),
+                u32::from_str_radix(hash.trim(), 16).unwrap(),
+            )
+        })
+        .collect();
+
+    let mut failures = Vec::new();
+    for (frame, hash) in &actual {
+        if expected.get(frame) != Some(hash) {
+            let picture = write_picture(name, *frame, &pictures[frame]);
+            let expected = match expected.get(frame) {
+                Some(hash) => format!("{:08X}", hash),
+                None => "no golden".to_string(),
+            };
+            failures.push(format!(
+                "frame {}: {:08X}, expected {}, see {}",
+                frame,
+                hash,
+                expected,
+                picture.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// write a rendered frame to `target/tmp/golden` to be looked at, returns its path
+fn write_picture(name: &str, frame: u64, pixels: &[u8]) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}_{:05}.png", name, frame));
+    let png = png::encode_gray(screen::WIDTH as u32, screen::HEIGHT as u32, pixels);
+    fs::write(&path, png).unwrap();
+    path
+}
+
+#[test]
+fn test_attract_mode() {
+    let rom = fs::read(ROM).unwrap();
+    let mut movie = Movie::new(&rom, Dips::default());
+    check(
+        "attract",
+        &mut movie,
+        &[60, 90, 120, 240, 400, 600, 900, 1200, 1600, 2000],
+    );
+}
+
+#[test]
+fn test_first_wave() {
+    let rom = fs::read(ROM).unwrap();
+    let mut movie = Movie::from_script(&rom, Dips::default(), FIRST_WAVE).unwrap();
+    check(
+        "first_wave",
+        &mut movie,
+        &[150, 200, 300, 400, 450, 500, 550, 600, 800],
+    );
+}
@@ -0,0 +1,357 @@
+//! focused tests of `Cpu::execute` by instruction class, each case a few bytes of
+//! machine code run from 0x0000 on a cpu with 64 KiB of ram
+//!
+//! flags are written as the letters of those set, in the order they sit in the
+//! processor status word: S, Z, A (aux carry), P and C
+
+use std::collections::BTreeMap;
+
+use invaders::cpu::{Cpu, Flags};
+
+/// run the instructions in `program` after `setup`, returns the cpu and the cycles taken
+fn run(program: &[u8], setup: impl FnOnce(&mut Cpu)) -> (Cpu, u64) {
+    let mut cpu = Cpu::new();
+    cpu.load(program, 0x0000);
+    cpu.sp = 0xF000;
+    setup(&mut cpu);
+    let mut instructions = 0;
+    let mut pc = 0;
+    while (pc as usize) < program.len() {
+        pc += cpu.fetch_at(pc).1 as u16;
+        instructions += 1;
+    }
+    for _ in 0..instructions {
+        cpu.step();
+    }
+    let cycles = cpu.cycles();
+    (cpu, cycles)
+}
+
+fn flags(flags: Flags) -> String {
+    [
+        (flags.s, 'S'),
+        (flags.z, 'Z'),
+        (flags.ac, 'A'),
+        (flags.p, 'P'),
+        (flags.cy, 'C'),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, letter)| letter)
+    .collect()
+}
+
+/// a processor status word with the given flags set
+fn psw(flags: &str) -> u8 {
+    flags.chars().fold(0x02, |psw, flag| {
+        psw | match flag {
+            'S' => 0x80,
+            'Z' => 0x40,
+            'A' => 0x10,
+            'P' => 0x04,
+            'C' => 0x01,
+            _ => panic!("unknown flag {}", flag),
+        }
+    })
+}
+
+/// (opcode with D as the source, a, operand, carry in, result in a, flags)
+type AluCase = (u8, u8, u8, bool, u8, &'static str);
+
+/// run each case with the operand in every source the opcode's low three bits
+/// can name, B, C, D, E, H, L, M and A, and check that every source of each
+/// operation got run, A only can be when the operand is a itself
+fn check_alu(cases: &[AluCase]) {
+    let mut run_sources = BTreeMap::<u8, u8>::new();
+    for &(opcode, a, operand, carry, result, expected) in cases {
+        for source in 0..8 {
+            if source == 7 && a != operand {
+                continue;
+            }
+            let opcode = opcode & 0xF8 | source;
+            let (cpu, cycles) = run(&[opcode], |cpu| {
+                cpu.set_hl(0x2040);
+                match source {
+                    0 => cpu.b = operand,
+                    1 => cpu.c = operand,
+                    2 => cpu.d = operand,
+                    3 => cpu.e = operand,
+                    4 => cpu.h = operand,
+                    5 => cpu.l = operand,
+                    6 => cpu.write_byte(0x2040, operand),
+                    _ => {}
+                }
+                cpu.a = a;
+                cpu.set_psw(psw(if carry { "C" } else { "" }));
+            });
+            let case = format!(
+                "opcode {:02X} with a {:02X}, operand {:02X}, carry {}",
+                opcode, a, operand, carry
+            );
+            assert_eq!(cpu.a, result, "{}", case);
+            assert_eq!(flags(cpu.flags()), expected, "{}", case);
+            assert_eq!(cycles, if source == 6 { 7 } else { 4 }, "{}", case);
+            *run_sources.entry(opcode & 0xF8).or_default() |= 1 << source;
+        }
+    }
+    for (operation, sources) in run_sources {
+        assert_eq!(
+            sources, 0xFF,
+            "not every source of {:02X} ran, give it a case with a equal to the operand",
+            operation
+        );
+    }
+}
+
+const ADD_D: u8 = 0x82;
+const ADC_D: u8 = 0x8A;
+const SUB_D: u8 = 0x92;
+const SBB_D: u8 = 0x9A;
+const CMP_D: u8 = 0xBA;
+
+#[test]
+fn test_add_adc() {
+    check_alu(&[
+        (ADD_D, 0x00, 0x00, false, 0x00, "ZP"),
+        (ADD_D, 0x0F, 0x01, false, 0x10, "A"),
+        (ADD_D, 0x7F, 0x01, false, 0x80, "SA"),
+        (ADD_D, 0xFF, 0x01, false, 0x00, "ZAPC"),
+        (ADD_D, 0x80, 0x80, false, 0x00, "ZPC"),
+        // ADD ignores the carry in
+        (ADD_D, 0x12, 0x21, true, 0x33, "P"),
+        (ADC_D, 0x12, 0x21, true, 0x34, ""),
+        (ADC_D, 0x0E, 0x01, true, 0x10, "A"),
+        (ADC_D, 0xFF, 0x00, true, 0x00, "ZAPC"),
+        (ADC_D, 0x7F, 0x7F, true, 0xFF, "SAP"),
+        (ADC_D, 0x80, 0x7F, true, 0x00, "ZAPC"),
+    ]);
+}
+
+#[test]
+fn test_sub_sbb_cmp() {
+    // the 8080 subtracts by adding the complement, so aux carry is set when the low
+    // nibble does not borrow
+    check_alu(&[
+        (SUB_D, 0x05, 0x05, false, 0x00, "ZAP"),
+        (SUB_D, 0x00, 0x01, false, 0xFF, "SPC"),
+        (SUB_D, 0x10, 0x01, false, 0x0F, "P"),
+        (SUB_D, 0x80, 0x01, false, 0x7F, ""),
+        (SUB_D, 0x3E, 0x3E, true, 0x00, "ZAP"),
+        (SBB_D, 0x05, 0x04, true, 0x00, "ZAP"),
+        (SBB_D, 0x00, 0x00, true, 0xFF, "SPC"),
+        (SBB_D, 0x00, 0xFF, true, 0x00, "ZPC"),
+        (SBB_D, 0x10, 0x01, false, 0x0F, "P"),
+        // CMP only sets flags
+        (CMP_D, 0x05, 0x06, false, 0x05, "SPC"),
+        (CMP_D, 0x42, 0x42, false, 0x42, "ZAP"),
+        (CMP_D, 0x42, 0x02, true, 0x42, "A"),
+    ]);
+}
+
+#[test]
+fn test_memory_operands() {
+    // ADD M with H and L different, which once read from (H, H)
+    let (cpu, cycles) = run(&[0x86], |cpu| {
+        cpu.a = 0x01;
+        cpu.set_hl(0x2040);
+        cpu.write_byte(0x2040, 0x41);
+        cpu.write_byte(0x2020, 0x99);
+    });
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cycles, 7);
+
+    // SUI, SBI and CPI take the operand from the instruction
+    let (cpu, _) = run(&[0xD6, 0x01, 0xDE, 0x01, 0xFE, 0x81], |cpu| cpu.a = 0x82);
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(flags(cpu.flags()), "SPC");
+}
+
+#[test]
+fn test_logical() {
+    // ANA sets aux carry from bit 3 of either operand, ORA and XRA clear it
+    check_alu(&[
+        (0xA2, 0x08, 0x00, true, 0x00, "ZAP"),
+        (0xA2, 0xF0, 0x3C, false, 0x30, "AP"),
+        (0xA2, 0x3C, 0x3C, true, 0x3C, "AP"),
+        (0xB2, 0x0F, 0xF0, true, 0xFF, "SP"),
+        (0xB2, 0x81, 0x81, true, 0x81, "SP"),
+        (0xAA, 0x5A, 0x5A, true, 0x00, "ZP"),
+    ]);
+}
+
+#[test]
+fn test_increment_decrement() {
+    // INR and DCR leave the carry alone
+    let (cpu, _) = run(&[0x14], |cpu| {
+        cpu.d = 0xFF;
+        cpu.set_psw(psw("C"));
+    });
+    assert_eq!(cpu.d, 0x00);
+    assert_eq!(flags(cpu.flags()), "ZAPC");
+
+    let (cpu, _) = run(&[0x15], |cpu| cpu.d = 0x00);
+    assert_eq!(cpu.d, 0xFF);
+    assert_eq!(flags(cpu.flags()), "SP");
+
+    let (cpu, _) = run(&[0x15], |cpu| cpu.d = 0x10);
+    assert_eq!(cpu.d, 0x0F);
+    assert_eq!(flags(cpu.flags()), "P");
+
+    // INX and DCX set no flags at all
+    let (cpu, _) = run(&[0x13, 0x1B, 0x1B], |cpu| cpu.set_de(0xFFFF));
+    assert_eq!(cpu.de(), 0xFFFE);
+    assert_eq!(flags(cpu.flags()), "");
+}
+
+#[test]
+fn test_rotates_through_carry() {
+    // (opcode, a, carry in, result, carry out)
+    let cases = [
+        (0x07, 0x80, false, 0x01, true), // RLC
+        (0x07, 0x41, true, 0x82, false),
+        (0x0F, 0x01, false, 0x80, true), // RRC
+        (0x0F, 0x82, true, 0x41, false),
+        (0x17, 0x80, false, 0x00, true), // RAL
+        (0x17, 0x01, true, 0x03, false),
+        (0x1F, 0x01, false, 0x00, true), // RAR
+        (0x1F, 0x00, true, 0x80, false),
+    ];
+    for (opcode, a, carry, result, carry_out) in cases {
+        // the other flags are left as they were
+        let before = if carry { "ZAPC" } else { "ZAP" };
+        let (cpu, cycles) = run(&[opcode], |cpu| {
+            cpu.a = a;
+            cpu.set_psw(psw(before));
+        });
+        assert_eq!(cpu.a, result, "opcode {:02X} with a {:02X}", opcode, a);
+        let expected = if carry_out { "ZAPC" } else { "ZAP" };
+        assert_eq!(flags(cpu.flags()), expected, "opcode {:02X}", opcode);
+        assert_eq!(cycles, 4);
+    }
+}
+
+#[test]
+fn test_dad_carry() {
+    // DAD B, D, H and SP only change the carry
+    let (cpu, cycles) = run(&[0x09], |cpu| {
+        cpu.set_hl(0xFFFF);
+        cpu.set_bc(0x0001);
+        cpu.set_psw(psw("SP"));
+    });
+    assert_eq!(cpu.hl(), 0x0000);
+    assert_eq!(flags(cpu.flags()), "SPC");
+    assert_eq!(cycles, 10);
+
+    let (cpu, _) = run(&[0x19], |cpu| {
+        cpu.set_hl(0x1234);
+        cpu.set_de(0x4321);
+        cpu.set_psw(psw("C"));
+    });
+    assert_eq!(cpu.hl(), 0x5555);
+    assert_eq!(flags(cpu.flags()), "");
+
+    let (cpu, _) = run(&[0x29], |cpu| cpu.set_hl(0x8001));
+    assert_eq!(cpu.hl(), 0x0002);
+    assert_eq!(flags(cpu.flags()), "C");
+
+    let (cpu, _) = run(&[0x39], |cpu| {
+        cpu.set_hl(0x0100);
+        cpu.sp = 0xFF00;
+    });
+    assert_eq!(cpu.hl(), 0x0000);
+    assert_eq!(flags(cpu.flags()), "C");
+}
+
+#[test]
+fn test_daa() {
+    let (cpu, _) = run(&[0x27], |cpu| cpu.a = 0x9B);
+    assert_eq!(cpu.a, 0x01);
+    assert_eq!(flags(cpu.flags()), "AC");
+
+    // 0x19 + 0x28 = 0x41, corrected to 47
+    let (cpu, _) = run(&[0xC6, 0x28, 0x27], |cpu| cpu.a = 0x19);
+    assert_eq!(cpu.a, 0x47);
+    assert!(!cpu.flags().cy);
+}
+
+#[test]
+fn test_xthl_pchl_sphl_xchg() {
+    let (cpu, cycles) = run(&[0xE3], |cpu| {
+        cpu.sp = 0x2000;
+        cpu.write_word(0x2000, 0x1234);
+        cpu.set_hl(0xABCD);
+    });
+    assert_eq!(cpu.hl(), 0x1234);
+    assert_eq!(cpu.read_word(0x2000), 0xABCD);
+    assert_eq!(cpu.sp, 0x2000);
+    assert_eq!(cycles, 18);
+
+    let (cpu, cycles) = run(&[0xE9], |cpu| cpu.set_hl(0x1234));
+    assert_eq!(cpu.pc, 0x1234);
+    assert_eq!(cycles, 5);
+
+    let (cpu, cycles) = run(&[0xF9], |cpu| cpu.set_hl(0x1234));
+    assert_eq!(cpu.sp, 0x1234);
+    assert_eq!(cycles, 5);
+
+    let (cpu, _) = run(&[0xEB], |cpu| {
+        cpu.set_de(0x1111);
+        cpu.set_hl(0x2222);
+    });
+    assert_eq!((cpu.de(), cpu.hl()), (0x2222, 0x1111));
+}
+
+#[test]
+fn test_conditional_branches() {
+    // each condition with the flags that make it true and false, in opcode order
+    let conditions = [
+        ("NZ", "", "Z"),
+        ("Z", "Z", ""),
+        ("NC", "", "C"),
+        ("C", "C", ""),
+        ("PO", "", "P"),
+        ("PE", "P", ""),
+        ("P", "", "S"),
+        ("M", "S", ""),
+    ];
+    for (i, (name, taken, not_taken)) in conditions.iter().enumerate() {
+        let row = 0xC0 + 8 * i as u8;
+        for (flags, is_taken) in [(taken, true), (not_taken, false)] {
+            let setup = |cpu: &mut Cpu| {
+                cpu.set_psw(psw(flags));
+                cpu.write_word(0xF000, 0x5678);
+            };
+            let case = format!("{} with flags {:?}", name, flags);
+
+            // Jcc always takes 10 cycles
+            let (cpu, cycles) = run(&[row + 2, 0x34, 0x12], setup);
+            assert_eq!(cpu.pc, if is_taken { 0x1234 } else { 0x0003 }, "J{}", case);
+            assert_eq!(cycles, 10, "J{}", case);
+
+            // Ccc takes 17 when it calls and 11 when it does not
+            let (cpu, cycles) = run(&[row + 4, 0x34, 0x12], setup);
+            assert_eq!(cpu.pc, if is_taken { 0x1234 } else { 0x0003 }, "C{}", case);
+            assert_eq!(cpu.sp, if is_taken { 0xEFFE } else { 0xF000 }, "C{}", case);
+            assert_eq!(cycles, if is_taken { 17 } else { 11 }, "C{}", case);
+
+            // Rcc takes 11 when it returns and 5 when it does not
+            let (cpu, cycles) = run(&[row], setup);
+            assert_eq!(cpu.pc, if is_taken { 0x5678 } else { 0x0001 }, "R{}", case);
+            assert_eq!(cycles, if is_taken { 11 } else { 5 }, "R{}", case);
+        }
+    }
+}
+
+#[test]
+fn test_stack_and_restarts() {
+    // PUSH PSW keeps bit 1 set and bits 3 and 5 clear whatever was popped
+    let (cpu, _) = run(&[0xF1, 0xF5], |cpu| cpu.write_word(0xF000, 0x42FF));
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cpu.read_word(cpu.sp), 0x42D7);
+
+    // RST 7 pushes the address after it and jumps to 0x0038
+    let (cpu, cycles) = run(&[0x00, 0xFF], |_| {});
+    assert_eq!(cpu.pc, 0x0038);
+    assert_eq!(cpu.read_word(cpu.sp), 0x0002);
+    assert_eq!(cycles, 4 + 11);
+}